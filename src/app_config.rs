@@ -1,12 +1,58 @@
-use serde::Deserialize;
-use std::{collections::HashMap, fmt, io, path::Path};
+use schemars::{JsonSchema, generate::SchemaSettings};
+use serde::{Deserialize, Deserializer, de};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use crate::{LogLevel, ProxyGroup, Rule, RuleSetBehavior, RuleTag, RunMode};
+use crate::{
+    LogLevel, Override, ProxyGroup, Rule, RuleSetBehavior, RuleTag, RunMode, SubscriptionOverrides,
+    config_loader::{env_var, load_config},
+};
+
+const DEFAULT_CACHE_DIR: &str = "./cache";
 
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
     Toml(toml::de::Error),
+    Yaml(serde_yaml::Error),
+    Json(serde_json::Error),
+    /// 合并后的配置中某个值无效
+    Invalid {
+        key: String,
+        message: String,
+    },
+    /// 字符串中引用的环境变量未设置且没有默认值
+    MissingVariable {
+        key: String,
+        variable: String,
+    },
+    /// `${file:...}` 引用的文件无法读取
+    SecretFile {
+        key: String,
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// 无法解析的 `${...}` 引用
+    InvalidInterpolation {
+        key: String,
+        expression: String,
+    },
+    /// `include` 引用的文件加载失败
+    Include {
+        path: PathBuf,
+        error: Box<Error>,
+    },
+    /// 文件之间循环引用
+    IncludeCycle(Vec<PathBuf>),
+    /// `include` 的值不是路径数组
+    InvalidInclude {
+        path: PathBuf,
+        message: String,
+    },
 }
 
 impl fmt::Display for Error {
@@ -14,6 +60,32 @@ impl fmt::Display for Error {
         match self {
             Error::Io(err) => write!(f, "io error: {err}"),
             Error::Toml(err) => write!(f, "toml error: {err}"),
+            Error::Yaml(err) => write!(f, "yaml error: {err}"),
+            Error::Json(err) => write!(f, "json error: {err}"),
+            Error::Invalid { key, message } => write!(f, "invalid value for `{key}`: {message}"),
+            Error::MissingVariable { key, variable } => write!(
+                f,
+                "environment variable `{variable}` referenced by `{key}` is not set"
+            ),
+            Error::SecretFile { key, path, error } => write!(
+                f,
+                "failed to read {} referenced by `{key}`: {error}",
+                path.display()
+            ),
+            Error::InvalidInterpolation { key, expression } => {
+                write!(f, "invalid interpolation `{expression}` in `{key}`")
+            }
+            Error::Include { path, error } => write!(f, "in {}: {error}", path.display()),
+            Error::IncludeCycle(paths) => {
+                let paths: Vec<_> = paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                write!(f, "include cycle: {}", paths.join(" -> "))
+            }
+            Error::InvalidInclude { path, message } => {
+                write!(f, "invalid include in {}: {message}", path.display())
+            }
         }
     }
 }
@@ -32,7 +104,56 @@ impl From<toml::de::Error> for Error {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl From<serde_yaml::Error> for Error {
+    fn from(value: serde_yaml::Error) -> Self {
+        Self::Yaml(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+/// 配置文件格式，三种格式使用相同的 kebab-case 字段名
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConfigFormat {
+    #[default]
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// 根据扩展名判断格式，无法识别时返回 `None`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "toml" => Ok(ConfigFormat::Toml),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(format!(
+                "unknown config format: {s} (expected toml, yaml or json)"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 #[serde(rename_all = "kebab-case")]
 pub enum RuleCfg {
@@ -40,7 +161,7 @@ pub enum RuleCfg {
     Set(RuleSetCfg),
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct RuleSingleCfg {
     pub tag: RuleTag,
     #[serde(default)]
@@ -58,7 +179,7 @@ impl From<RuleSingleCfg> for Rule {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct RuleSetCfg {
     pub name: String,
     pub url: String,
@@ -68,11 +189,15 @@ pub struct RuleSetCfg {
     pub interval: Option<u64>,
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct AppConfig {
+    /// 合并到本文件之前的其他配置文件，支持 glob 通配符，相对路径基于本文件所在目录
     #[serde(default)]
-    pub proxies: HashMap<String, String>,
+    pub include: Vec<String>,
+    /// 订阅源，值为订阅链接或带有单独设置的表
+    #[serde(default)]
+    pub proxies: HashMap<String, ProxySource>,
     #[serde(default)]
     pub groups: Vec<ProxyGroup>,
     #[serde(default)]
@@ -87,9 +212,31 @@ pub struct AppConfig {
     pub provider_config: Option<ProviderConfig>,
     #[serde(default)]
     pub auth: Option<AuthConfig>,
+    #[serde(default)]
+    pub profiles: HashMap<String, ProfileConfig>,
+    #[serde(default)]
+    pub server: Option<ServerConfig>,
+}
+
+/// 命名配置档，继承基础配置并覆盖部分字段
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProfileConfig {
+    /// 替换基础配置中的代理组
+    #[serde(default)]
+    pub groups: Option<Vec<ProxyGroup>>,
+    /// 替换基础配置中的规则
+    #[serde(default)]
+    pub rules: Option<Vec<RuleCfg>>,
+    /// 按字段覆盖基础配置中的默认配置
+    #[serde(default)]
+    pub default_config: Option<DefaultConfig>,
+    /// 替换基础配置中的地区代理组配置
+    #[serde(default)]
+    pub region_groups: Option<RegionGroupConfig>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct RegionGroupConfig {
     pub enabled: bool,
@@ -112,7 +259,7 @@ impl Default for RegionGroupConfig {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct RegionTemplate {
     pub name: String,
@@ -123,7 +270,7 @@ pub struct RegionTemplate {
     pub icon: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct DefaultConfig {
     #[serde(default)]
@@ -140,7 +287,21 @@ pub struct DefaultConfig {
     pub allow_lan: Option<bool>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl DefaultConfig {
+    /// 用 `other` 中已设置的字段覆盖当前配置
+    pub fn merged_with(&self, other: &DefaultConfig) -> DefaultConfig {
+        DefaultConfig {
+            port: other.port.or(self.port),
+            socks_port: other.socks_port.or(self.socks_port),
+            mixed_port: other.mixed_port.or(self.mixed_port),
+            mode: other.mode.or(self.mode),
+            log_level: other.log_level.or(self.log_level),
+            allow_lan: other.allow_lan.or(self.allow_lan),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct ProviderConfig {
     #[serde(default)]
@@ -153,13 +314,265 @@ pub struct ProviderConfig {
     pub lazy: Option<bool>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// 订阅源，可以只写订阅链接，也可以写成带有单独设置的表
+#[derive(Debug, Clone, JsonSchema)]
+#[schemars(untagged)]
+pub enum ProxySource {
+    Url(String),
+    Provider(Box<ProxySourceConfig>),
+}
+
+/// 单个订阅源的设置，未设置的字段使用 `provider-config` 中的值
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProxySourceConfig {
+    pub url: String,
+    /// 更新间隔（秒）
+    #[serde(default)]
+    pub interval: Option<u64>,
+    #[serde(default)]
+    pub health_check: Option<HealthCheckConfig>,
+    /// 只保留名称匹配该正则的节点
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// 排除名称匹配该正则的节点
+    #[serde(default)]
+    pub exclude_filter: Option<String>,
+    /// 排除的节点类型，以 `|` 分隔
+    #[serde(default)]
+    pub exclude_type: Option<String>,
+    /// 请求订阅时附加的请求头，如自定义 User-Agent，中转订阅时同样生效
+    #[serde(default)]
+    pub header: Option<BTreeMap<String, Vec<String>>>,
+    /// 请求订阅时经过的代理或代理组，只对客户端生效，中转订阅时本服务直接访问上游
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// 订阅内容的大小上限（字节），中转订阅时同样生效
+    #[serde(default)]
+    pub size_limit: Option<u64>,
+    #[serde(default)]
+    pub r#override: Option<Override>,
+}
+
+/// 单个订阅源的健康检查设置
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct HealthCheckConfig {
+    #[serde(default)]
+    pub enable: Option<bool>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub interval: Option<u64>,
+    #[serde(default)]
+    pub lazy: Option<bool>,
+}
+
+impl ProxySource {
+    pub fn url(&self) -> &str {
+        match self {
+            ProxySource::Url(url) => url,
+            ProxySource::Provider(config) => &config.url,
+        }
+    }
+
+    /// 替换订阅链接，保留其他设置
+    pub fn set_url(&mut self, url: String) {
+        match self {
+            ProxySource::Url(current) => *current = url,
+            ProxySource::Provider(config) => config.url = url,
+        }
+    }
+
+    /// 表形式的设置，只写链接时返回 `None`
+    pub fn config(&self) -> Option<&ProxySourceConfig> {
+        match self {
+            ProxySource::Url(_) => None,
+            ProxySource::Provider(config) => Some(config.as_ref()),
+        }
+    }
+}
+
+impl From<String> for ProxySource {
+    fn from(url: String) -> Self {
+        ProxySource::Url(url)
+    }
+}
+
+impl From<&str> for ProxySource {
+    fn from(url: &str) -> Self {
+        ProxySource::Url(url.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ProxySource {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct SourceVisitor;
+
+        impl<'de> de::Visitor<'de> for SourceVisitor {
+            type Value = ProxySource;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a subscription URL or a provider table")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                Ok(ProxySource::Url(value.to_string()))
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                ProxySourceConfig::deserialize(de::value::MapAccessDeserializer::new(map))
+                    .map(|config| ProxySource::Provider(Box::new(config)))
+            }
+        }
+
+        deserializer.deserialize_any(SourceVisitor)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct AuthConfig {
     #[serde(default)]
     pub enabled: bool,
     #[serde(default)]
     pub token: Option<String>,
+    /// 为每个用户或设备单独分配的 token
+    #[serde(default)]
+    pub tokens: Vec<TokenEntry>,
+    /// 签名链接使用的密钥，未设置时不接受签名链接
+    #[serde(default)]
+    pub signing_key: Option<String>,
+}
+
+/// 单个 token 的配置
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct TokenEntry {
+    /// 用于识别 token 持有者的名称
+    pub label: String,
+    pub token: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// 过期时间（unix 时间戳，秒）
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// 绑定的配置档，设置后该 token 只能访问此配置档
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// 对该 token 生效的订阅源覆盖
+    #[serde(default)]
+    pub overrides: Option<SubscriptionOverrides>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct ServerConfig {
+    /// 对外访问地址，例如 `https://sub.example.com`，未设置时根据请求头推断
+    #[serde(default)]
+    pub public_url: Option<String>,
+    /// 是否通过 `/providers/{name}` 中转订阅，避免暴露上游订阅链接，默认开启
+    #[serde(default = "default_true")]
+    pub relay_providers: bool,
+    /// 是否通过 `/rules/{name}` 镜像规则集
+    #[serde(default)]
+    pub mirror_rules: bool,
+    /// 缓存目录，默认为 `./cache`
+    #[serde(default)]
+    pub cache_dir: Option<PathBuf>,
+    /// 是否信任 `X-Forwarded-For`、`X-Forwarded-Host` 和 `X-Forwarded-Proto` 请求头，仅在反向代理之后启用
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+    /// 认证接口的限流配置
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// 订阅访问审计日志
+    #[serde(default)]
+    pub audit_log: Option<AuditLogConfig>,
+    /// HTTPS 证书，命令行参数 `--tls-cert`/`--tls-key` 优先
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+/// PEM 格式的证书和私钥路径，文件更新后自动重新加载
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+pub struct TlsConfig {
+    /// 证书链
+    pub cert: PathBuf,
+    /// 私钥
+    pub key: PathBuf,
+}
+
+/// 限流配置，未设置的限制不生效
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct RateLimitConfig {
+    /// 每个 IP 每分钟允许的请求数
+    #[serde(default)]
+    pub per_ip: Option<u32>,
+    /// 每个 token 每分钟允许的请求数
+    #[serde(default)]
+    pub per_token: Option<u32>,
+    /// 同一 IP 认证失败多少次后锁定
+    #[serde(default)]
+    pub max_failed_attempts: Option<u32>,
+    /// 锁定时长（秒）
+    #[serde(default = "default_lockout_seconds")]
+    pub lockout_seconds: u64,
+}
+
+fn default_lockout_seconds() -> u64 {
+    900
+}
+
+/// 审计日志配置，每个请求写入一行 JSON
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditLogConfig {
+    /// 日志文件路径
+    pub path: PathBuf,
+    /// 单个文件的最大字节数，超过后轮转
+    #[serde(default = "default_audit_log_max_size")]
+    pub max_size: u64,
+    /// 保留的历史文件数量
+    #[serde(default = "default_audit_log_max_files")]
+    pub max_files: u32,
+}
+
+fn default_audit_log_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_audit_log_max_files() -> u32 {
+    5
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            public_url: None,
+            relay_providers: true,
+            mirror_rules: false,
+            cache_dir: None,
+            trust_forwarded_for: false,
+            rate_limit: None,
+            audit_log: None,
+            tls: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// 规则集镜像的缓存目录
+    pub fn rule_cache_dir(&self) -> PathBuf {
+        self.cache_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CACHE_DIR))
+            .join("rules")
+    }
 }
 
 fn default_true() -> bool {
@@ -208,10 +621,65 @@ pub fn get_default_region_templates() -> Vec<RegionTemplate> {
 }
 
 impl AppConfig {
+    /// 从配置文件加载配置，按扩展名识别 TOML、YAML 和 JSON，无法识别时按 TOML 解析
+    ///
+    /// 先按顺序合并 `include` 中的文件，再合并本文件：同名的表递归合并，
+    /// 元素都是表的数组（如 `[[rules]]`）依次追加，其他值以后加载的为准。
+    ///
+    /// 字符串值中的 `${VAR}`、`${VAR:-default}` 和 `${file:/path}` 会在解析时替换，
+    /// 相对的文件路径基于引用它的文件所在目录。
     pub fn load_from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
-        let data = std::fs::read(path)?;
-        let cfg = toml::from_slice(&data)?;
-        Ok(cfg)
+        Self::load_from_file_with_format(path, None)
+    }
+
+    /// 以指定格式加载配置文件，`None` 表示按扩展名识别
+    ///
+    /// 指定的格式只作用于该文件，`include` 的文件仍按各自的扩展名识别。
+    pub fn load_from_file_with_format(
+        path: impl AsRef<Path>,
+        format: Option<ConfigFormat>,
+    ) -> Result<Self, Error> {
+        load_config(path.as_ref(), format, &env_var).0
+    }
+
+    /// 配置文件的 JSON Schema，供编辑器补全和校验
+    ///
+    /// 使用 Taplo 和 YAML 语言服务器都支持的 draft-07。
+    pub fn json_schema() -> serde_json::Value {
+        SchemaSettings::draft07()
+            .into_generator()
+            .into_root_schema_for::<AppConfig>()
+            .to_value()
+    }
+
+    /// 生成指定配置档的完整配置
+    ///
+    /// 返回的配置只用于生成订阅，不再包含其他配置档和认证配置。配置档不存在时返回 `None`。
+    pub fn with_profile(&self, name: &str) -> Option<AppConfig> {
+        let profile = self.profiles.get(name)?;
+        let mut config = AppConfig {
+            profiles: HashMap::new(),
+            auth: None,
+            ..self.clone()
+        };
+
+        if let Some(groups) = &profile.groups {
+            config.groups = groups.clone();
+        }
+        if let Some(rules) = &profile.rules {
+            config.rules = rules.clone();
+        }
+        if let Some(overrides) = &profile.default_config {
+            config.default_config = Some(match &self.default_config {
+                Some(base) => base.merged_with(overrides),
+                None => overrides.clone(),
+            });
+        }
+        if let Some(region_groups) = &profile.region_groups {
+            config.region_groups = Some(region_groups.clone());
+        }
+
+        Some(config)
     }
 }
 #[cfg(test)]
@@ -323,7 +791,7 @@ interval = 3600
         
         // 检查订阅源
         assert_eq!(config.proxies.len(), 1);
-        assert_eq!(config.proxies.get("test-provider"), Some(&"https://example.com/clash".to_string()));
+        assert_eq!(config.proxies["test-provider"].url(), "https://example.com/clash");
         
         // 检查代理组
         assert_eq!(config.groups.len(), 1);
@@ -480,6 +948,58 @@ lazy = true
         assert_eq!(config.update_interval, None);
     }
 
+    #[test]
+    fn test_proxy_source_deserialization() {
+        let toml_content = r#"
+[proxies]
+plain = "https://example.com/plain"
+
+[proxies.custom]
+url = "https://example.com/custom"
+interval = 600
+exclude-filter = "过期|剩余"
+size-limit = 1048576
+header = { User-Agent = ["clash.meta"] }
+health-check = { interval = 120 }
+override = { additional-prefix = "[A] ", udp = true }
+"#;
+
+        let config: AppConfig = toml::from_str(toml_content).unwrap();
+        let plain = &config.proxies["plain"];
+        assert_eq!(plain.url(), "https://example.com/plain");
+        assert!(plain.config().is_none());
+
+        let custom = config.proxies["custom"].config().unwrap();
+        assert_eq!(custom.url, "https://example.com/custom");
+        assert_eq!(custom.interval, Some(600));
+        assert_eq!(custom.exclude_filter.as_deref(), Some("过期|剩余"));
+        assert_eq!(custom.size_limit, Some(1048576));
+        let header = custom.header.as_ref().unwrap();
+        assert_eq!(header["User-Agent"], vec!["clash.meta"]);
+        let health_check = custom.health_check.as_ref().unwrap();
+        assert_eq!(health_check.interval, Some(120));
+        assert_eq!(health_check.url, None);
+        let r#override = custom.r#override.as_ref().unwrap();
+        assert_eq!(r#override.additional_prefix.as_deref(), Some("[A] "));
+        assert_eq!(r#override.udp, Some(true));
+
+        // 替换链接时保留其他设置
+        let mut source = config.proxies["custom"].clone();
+        source.set_url("https://example.com/new".to_string());
+        assert_eq!(source.url(), "https://example.com/new");
+        assert_eq!(source.config().unwrap().interval, Some(600));
+
+        // 表中缺少 url
+        let err = toml::from_str::<AppConfig>("[proxies.a]\ninterval = 600\n").unwrap_err();
+        assert!(err.to_string().contains("missing field `url`"), "{err}");
+        let err = toml::from_str::<AppConfig>("[proxies]\na = 1\n").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("a subscription URL or a provider table"),
+            "{err}"
+        );
+    }
+
     #[test]
     fn test_auth_config_deserialization() {
         let toml_content = r#"
@@ -503,6 +1023,90 @@ enabled = false
         assert_eq!(config.token, None);
     }
 
+    #[test]
+    fn test_app_config_with_profile() {
+        let config_content = r#"
+[default-config]
+mixed-port = 7890
+mode = "rule"
+
+[proxies]
+test = "https://example.com/clash"
+
+[[groups]]
+name = "Proxies"
+type = "select"
+proxies = ["DIRECT"]
+
+[[rules]]
+type = "single"
+tag = "MATCH"
+target = "Proxies"
+
+[profiles.router]
+default-config = { mixed-port = 7893, allow-lan = true }
+
+[[profiles.router.rules]]
+type = "single"
+tag = "MATCH"
+target = "DIRECT"
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(config_content.as_bytes()).unwrap();
+
+        let config = AppConfig::load_from_file(temp_file.path()).unwrap();
+        assert_eq!(config.profiles.len(), 1);
+        assert!(config.with_profile("missing").is_none());
+
+        let router = config.with_profile("router").unwrap();
+        assert!(router.profiles.is_empty());
+
+        // 默认配置按字段覆盖
+        let default_config = router.default_config.unwrap();
+        assert_eq!(default_config.mixed_port, Some(7893));
+        assert_eq!(default_config.allow_lan, Some(true));
+        assert_eq!(default_config.mode, Some(RunMode::Rule));
+
+        // 规则被替换，代理组和订阅源继承自基础配置
+        assert_eq!(router.rules.len(), 1);
+        match &router.rules[0] {
+            RuleCfg::Single(rule) => assert_eq!(rule.target, "DIRECT"),
+            _ => panic!("Expected Single rule"),
+        }
+        assert_eq!(router.groups.len(), 1);
+        assert_eq!(router.proxies.len(), 1);
+    }
+
+    #[test]
+    fn test_server_config_deserialization() {
+        let toml_content = r#"
+public-url = "https://sub.example.com"
+relay-providers = false
+"#;
+
+        let config: ServerConfig = toml::from_str(toml_content).unwrap();
+        assert_eq!(config.public_url, Some("https://sub.example.com".to_string()));
+        assert!(!config.relay_providers);
+
+        let config: ServerConfig = toml::from_str("").unwrap();
+        assert!(config.public_url.is_none());
+        assert!(config.relay_providers);
+        assert!(ServerConfig::default().relay_providers);
+        assert!(!config.mirror_rules);
+        assert!(!config.trust_forwarded_for);
+        assert!(config.rate_limit.is_none());
+        assert!(config.audit_log.is_none());
+        assert!(config.tls.is_none());
+        assert_eq!(config.rule_cache_dir(), PathBuf::from("./cache/rules"));
+
+        let config: ServerConfig = toml::from_str(r#"cache-dir = "/var/cache/sub-util""#).unwrap();
+        assert_eq!(
+            config.rule_cache_dir(),
+            PathBuf::from("/var/cache/sub-util/rules")
+        );
+    }
+
     #[test]
     fn test_app_config_with_auth() {
         let config_content = r#"
@@ -527,4 +1131,245 @@ test = "https://example.com/clash"
         // 检查其他配置
         assert_eq!(config.proxies.len(), 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_auth_token_entries_deserialization() {
+        let toml_content = r#"
+enabled = true
+
+[[tokens]]
+label = "alice-phone"
+token = "alice-secret"
+expires-at = 1893456000
+profile = "phone"
+overrides = { only = ["a"] }
+
+[[tokens]]
+label = "bob"
+token = "bob-secret"
+enabled = false
+"#;
+
+        let auth_config: AuthConfig = toml::from_str(toml_content).unwrap();
+        assert!(auth_config.token.is_none());
+        assert_eq!(auth_config.tokens.len(), 2);
+
+        let alice = &auth_config.tokens[0];
+        assert_eq!(alice.label, "alice-phone");
+        assert!(alice.enabled);
+        assert_eq!(alice.expires_at, Some(1893456000));
+        assert_eq!(alice.profile.as_deref(), Some("phone"));
+        assert_eq!(
+            alice.overrides.as_ref().unwrap().only,
+            Some(vec!["a".to_string()])
+        );
+
+        let bob = &auth_config.tokens[1];
+        assert!(!bob.enabled);
+        assert!(bob.profile.is_none() && bob.overrides.is_none());
+    }
+
+    #[test]
+    fn test_rate_limit_config_deserialization() {
+        let toml_content = r#"
+trust-forwarded-for = true
+
+[rate-limit]
+per-ip = 30
+max-failed-attempts = 5
+"#;
+
+        let config: ServerConfig = toml::from_str(toml_content).unwrap();
+        assert!(config.trust_forwarded_for);
+        let rate_limit = config.rate_limit.unwrap();
+        assert_eq!(rate_limit.per_ip, Some(30));
+        assert_eq!(rate_limit.per_token, None);
+        assert_eq!(rate_limit.max_failed_attempts, Some(5));
+        assert_eq!(rate_limit.lockout_seconds, 900);
+    }
+
+    #[test]
+    fn test_audit_log_config_deserialization() {
+        let toml_content = r#"
+[audit-log]
+path = "/var/log/sub-util/audit.jsonl"
+max-files = 2
+"#;
+
+        let config: ServerConfig = toml::from_str(toml_content).unwrap();
+        let audit_log = config.audit_log.unwrap();
+        assert_eq!(audit_log.path, PathBuf::from("/var/log/sub-util/audit.jsonl"));
+        assert_eq!(audit_log.max_size, 10 * 1024 * 1024);
+        assert_eq!(audit_log.max_files, 2);
+    }
+
+    #[test]
+    fn test_tls_config_deserialization() {
+        let toml_content = r#"
+[tls]
+cert = "/etc/sub-util/fullchain.pem"
+key = "/etc/sub-util/privkey.pem"
+"#;
+
+        let config: ServerConfig = toml::from_str(toml_content).unwrap();
+        assert_eq!(
+            config.tls,
+            Some(TlsConfig {
+                cert: PathBuf::from("/etc/sub-util/fullchain.pem"),
+                key: PathBuf::from("/etc/sub-util/privkey.pem"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_load_from_file_interpolation() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("sub-token"), "abc\n").unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+[proxies]
+a = "https://example.com/sub?token=${file:sub-token}"
+b = "https://example.com/${SUB_UTIL_TEST_UNSET_PATH:-default}"
+"#,
+        )
+        .unwrap();
+
+        let config = AppConfig::load_from_file(&path).unwrap();
+        assert_eq!(
+            config.proxies["a"].url(),
+            "https://example.com/sub?token=abc"
+        );
+        assert_eq!(config.proxies["b"].url(), "https://example.com/default");
+
+        std::fs::write(&path, "[proxies]\na = \"${SUB_UTIL_TEST_UNSET}\"\n").unwrap();
+        let err = AppConfig::load_from_file(&path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "environment variable `SUB_UTIL_TEST_UNSET` referenced by `proxies.a` is not set"
+        );
+
+        // 类型错误仍然报告行号
+        std::fs::write(&path, "[proxies]\na = 1\n").unwrap();
+        let err = AppConfig::load_from_file(&path).unwrap_err();
+        assert!(matches!(err, Error::Toml(_)));
+        assert!(err.to_string().contains("line 2"), "{err}");
+    }
+
+    #[test]
+    fn test_load_yaml_and_json() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let yaml = dir.path().join("config.yaml");
+        std::fs::write(
+            &yaml,
+            r#"
+proxies:
+  a: https://example.com/sub
+provider-config:
+  update-interval: 7200
+groups:
+  - name: A
+    type: select
+    proxies: [DIRECT]
+rules:
+  - type: single
+    tag: DOMAIN
+    value: example.com
+    target: A
+"#,
+        )
+        .unwrap();
+        let config = AppConfig::load_from_file(&yaml).unwrap();
+        assert_eq!(config.proxies["a"].url(), "https://example.com/sub");
+        assert_eq!(config.provider_config.unwrap().update_interval, Some(7200));
+        assert_eq!(config.groups.len(), 1);
+        assert_eq!(config.rules.len(), 1);
+
+        let json = dir.path().join("config.json");
+        std::fs::write(
+            &json,
+            r#"{"proxies": {"a": "https://example.com/sub"}, "provider-config": {"lazy": true}}"#,
+        )
+        .unwrap();
+        let config = AppConfig::load_from_file(&json).unwrap();
+        assert_eq!(config.provider_config.unwrap().lazy, Some(true));
+
+        // 扩展名无法识别时可以指定格式
+        let other = dir.path().join("config.conf");
+        std::fs::copy(&yaml, &other).unwrap();
+        assert!(AppConfig::load_from_file(&other).is_err());
+        let config =
+            AppConfig::load_from_file_with_format(&other, Some(ConfigFormat::Yaml)).unwrap();
+        assert_eq!(config.proxies.len(), 1);
+
+        // 错误信息带有行号
+        std::fs::write(&yaml, "proxies:\n  a: https://example.com/sub\ngroups: 1\n").unwrap();
+        let err = AppConfig::load_from_file(&yaml).unwrap_err();
+        assert!(matches!(err, Error::Yaml(_)), "{err:?}");
+        assert!(err.to_string().contains("line 3"), "{err}");
+
+        std::fs::write(&json, "{\n  \"proxies\": []\n}").unwrap();
+        let err = AppConfig::load_from_file(&json).unwrap_err();
+        assert!(matches!(err, Error::Json(_)), "{err:?}");
+        assert!(err.to_string().contains("line 2"), "{err}");
+    }
+
+    #[test]
+    fn test_config_format() {
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("a.toml")),
+            Some(ConfigFormat::Toml)
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("a.YML")),
+            Some(ConfigFormat::Yaml)
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("a.json")),
+            Some(ConfigFormat::Json)
+        );
+        assert_eq!(ConfigFormat::from_path(Path::new("config")), None);
+        assert_eq!("yaml".parse(), Ok(ConfigFormat::Yaml));
+        assert!("ini".parse::<ConfigFormat>().is_err());
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema = AppConfig::json_schema();
+        assert_eq!(schema["$schema"], "http://json-schema.org/draft-07/schema#");
+        // 字段名与配置文件一致
+        let properties = schema["properties"].as_object().unwrap();
+        for key in ["include", "proxies", "groups", "rules", "region-groups"] {
+            assert!(properties.contains_key(key), "{key}");
+        }
+
+        let definitions = &schema["definitions"];
+        let rule_tags = definitions["RuleTag"]["enum"].as_array().unwrap();
+        assert!(rule_tags.contains(&"DOMAIN-SUFFIX".into()));
+        assert!(rule_tags.contains(&"IP-CIDR6".into()));
+        assert_eq!(
+            definitions["RuleSetBehavior"]["enum"],
+            serde_json::json!(["domain", "ipcidr", "classical"])
+        );
+        let log_levels = definitions["LogLevel"]["enum"].as_array().unwrap();
+        assert!(log_levels.contains(&"silent".into()));
+        assert!(definitions["DefaultConfig"]["properties"]["mixed-port"].is_object());
+        assert!(definitions["AuthConfig"]["properties"]["signing-key"].is_object());
+        // 订阅源可以是字符串或表
+        let sources = definitions["ProxySource"]["anyOf"].as_array().unwrap();
+        assert_eq!(sources[0]["type"], "string");
+        assert!(definitions["ProxySourceConfig"]["properties"]["exclude-filter"].is_object());
+        // 代理组按 `type` 区分
+        let group_types: Vec<_> = definitions["ProxyGroup"]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variant| variant["properties"]["type"]["const"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            group_types,
+            vec!["relay", "url-test", "fallback", "load-balance", "select"]
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::SystemTime,
+};
+
+use serde::{Serialize, Serializer};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::AuditLogConfig;
+
+/// 审计日志中的一条记录
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: SystemTime,
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    /// 匹配的路由（如 `/subs/{profile}`），不包含路径中的 token
+    pub route: Option<String>,
+    /// 认证使用的 token 名称，从不记录 token 本身
+    pub token_label: Option<String>,
+    pub profile: Option<String>,
+    pub status: u16,
+    /// 返回内容的 SHA-256 摘要
+    pub config_hash: Option<String>,
+}
+
+/// 追加写入 JSON Lines 格式的审计日志，按文件大小轮转
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    writer: Mutex<Option<AuditWriter>>,
+}
+
+#[derive(Debug)]
+struct AuditWriter {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl AuditWriter {
+    fn open(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+        })
+    }
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 写入一条记录，配置中的路径变化后会切换到新文件
+    pub fn record(&self, config: &AuditLogConfig, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let mut writer = self.writer.lock().unwrap();
+        if writer
+            .as_ref()
+            .is_none_or(|writer| writer.path != config.path)
+        {
+            *writer = Some(AuditWriter::open(&config.path)?);
+        }
+        let current = writer.as_mut().unwrap();
+
+        if current.size > 0 && current.size + line.len() as u64 > config.max_size {
+            // 先关闭当前文件再轮转
+            *writer = None;
+            rotate(&config.path, config.max_files)?;
+            *writer = Some(AuditWriter::open(&config.path)?);
+        }
+
+        let current = writer.as_mut().unwrap();
+        current.file.write_all(&line)?;
+        current.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// 将已写入的记录同步到磁盘
+    pub fn flush(&self) -> io::Result<()> {
+        match self.writer.lock().unwrap().as_mut() {
+            Some(writer) => writer.file.sync_data(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// 在单独的线程中写入审计日志，请求处理不必等待磁盘读写
+#[derive(Debug, Clone)]
+pub struct AuditQueue {
+    sender: mpsc::Sender<AuditMessage>,
+}
+
+#[derive(Debug)]
+enum AuditMessage {
+    Record(AuditLogConfig, AuditRecord),
+    Flush(oneshot::Sender<io::Result<()>>),
+}
+
+impl AuditQueue {
+    /// 启动写入线程，最多排队 `capacity` 条记录，所有句柄被丢弃后线程退出
+    pub fn spawn(log: AuditLog, capacity: usize) -> io::Result<Self> {
+        let (sender, mut receiver) = mpsc::channel(capacity);
+        thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || {
+                while let Some(message) = receiver.blocking_recv() {
+                    match message {
+                        AuditMessage::Record(config, record) => {
+                            if let Err(err) = log.record(&config, &record) {
+                                tracing::error!(
+                                    "Failed to write audit log {}: {}",
+                                    config.path.display(),
+                                    err
+                                );
+                            }
+                        }
+                        AuditMessage::Flush(done) => {
+                            let _ = done.send(log.flush());
+                        }
+                    }
+                }
+            })?;
+        Ok(Self { sender })
+    }
+
+    /// 将一条记录加入写入队列，队列已满时等待
+    pub async fn record(&self, config: AuditLogConfig, record: AuditRecord) {
+        // 写入线程在所有句柄被丢弃前不会退出
+        let _ = self.sender.send(AuditMessage::Record(config, record)).await;
+    }
+
+    /// 等待此前排队的记录全部写入并同步到磁盘
+    pub async fn flush(&self) -> io::Result<()> {
+        let (done, result) = oneshot::channel();
+        if self.sender.send(AuditMessage::Flush(done)).await.is_err() {
+            return Ok(());
+        }
+        result.await.unwrap_or(Ok(()))
+    }
+}
+
+/// 将 `audit.jsonl` 依次重命名为 `audit.jsonl.1`、`audit.jsonl.2`……，超出数量的旧文件被删除
+fn rotate(path: &Path, max_files: u32) -> io::Result<()> {
+    let rotated = |index: u32| {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    };
+    let ignore_missing = |result: io::Result<()>| match result {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    };
+
+    if max_files == 0 {
+        return ignore_missing(fs::remove_file(path));
+    }
+    ignore_missing(fs::remove_file(rotated(max_files)))?;
+    for index in (1..max_files).rev() {
+        ignore_missing(fs::rename(rotated(index), rotated(index + 1)))?;
+    }
+    fs::rename(path, rotated(1))
+}
+
+fn serialize_timestamp<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    // RFC 3339 UTC 时间，精确到毫秒
+    serializer.collect_str(&humantime::format_rfc3339_millis(*time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn record(status: u16) -> AuditRecord {
+        AuditRecord {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            ip: Some(IpAddr::from([192, 0, 2, 1])),
+            user_agent: Some("clash.meta".to_string()),
+            route: Some("/subs/{profile}".to_string()),
+            token_label: Some("alice-phone".to_string()),
+            profile: Some("router".to_string()),
+            status,
+            config_hash: Some("abc".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_record_writes_json_lines() {
+        let dir = TempDir::new().unwrap();
+        let config = AuditLogConfig {
+            path: dir.path().join("logs/audit.jsonl"),
+            max_size: 1024 * 1024,
+            max_files: 1,
+        };
+
+        let log = AuditLog::new();
+        log.record(&config, &record(200)).unwrap();
+        log.record(&config, &record(401)).unwrap();
+        log.flush().unwrap();
+
+        let content = fs::read_to_string(&config.path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["timestamp"], "2023-11-14T22:13:20.123Z");
+        assert_eq!(lines[0]["ip"], "192.0.2.1");
+        assert_eq!(lines[0]["token_label"], "alice-phone");
+        assert_eq!(lines[0]["status"], 200);
+        assert_eq!(lines[1]["status"], 401);
+    }
+
+    #[test]
+    fn test_record_rotates_by_size() {
+        let dir = TempDir::new().unwrap();
+        let line_len = serde_json::to_vec(&record(200)).unwrap().len() as u64 + 1;
+        let config = AuditLogConfig {
+            path: dir.path().join("audit.jsonl"),
+            max_size: line_len * 2,
+            max_files: 2,
+        };
+        let rotated = |index: u32| dir.path().join(format!("audit.jsonl.{index}"));
+
+        let log = AuditLog::new();
+        for _ in 0..7 {
+            log.record(&config, &record(200)).unwrap();
+        }
+
+        // 每个文件两行，只保留两个历史文件
+        let lines = |path: &Path| fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(lines(&config.path), 1);
+        assert_eq!(lines(&rotated(1)), 2);
+        assert_eq!(lines(&rotated(2)), 2);
+        assert!(!rotated(3).exists());
+    }
+
+    #[tokio::test]
+    async fn test_queue_flush_drains_records() {
+        let dir = TempDir::new().unwrap();
+        let config = AuditLogConfig {
+            path: dir.path().join("audit.jsonl"),
+            max_size: 1024 * 1024,
+            max_files: 1,
+        };
+
+        let queue = AuditQueue::spawn(AuditLog::new(), 1).unwrap();
+        for status in [200, 401, 404] {
+            queue.record(config.clone(), record(status)).await;
+        }
+        queue.flush().await.unwrap();
+
+        // flush 返回时排在它之前的记录都已写入
+        let content = fs::read_to_string(&config.path).unwrap();
+        let statuses: Vec<u64> = content
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<serde_json::Value>(line).unwrap()["status"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(statuses, vec![200, 401, 404]);
+    }
+}
@@ -0,0 +1,621 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use argon2::{
+    Argon2,
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
+};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::{AppConfig, TokenEntry};
+
+const SHA256_PREFIX: &str = "sha256:";
+const ARGON2_PREFIX: &str = "$argon2";
+/// 最多缓存多少个通过 argon2 校验的 token
+const VERIFIED_TOKEN_CAPACITY: usize = 1024;
+
+/// token 校验失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    /// 启用了认证但没有配置 token
+    ServerMisconfigured,
+    /// 请求中没有 token
+    TokenRequired,
+    /// token 不正确或已被禁用
+    InvalidToken,
+    /// token 已过期
+    TokenExpired,
+    /// 签名链接的签名不正确
+    InvalidSignature,
+    /// 签名链接已过期
+    LinkExpired,
+}
+
+impl AuthError {
+    /// 失败原因的名称，用于监控指标
+    pub fn reason(&self) -> &'static str {
+        match self {
+            AuthError::ServerMisconfigured => "server_misconfigured",
+            AuthError::TokenRequired => "token_required",
+            AuthError::InvalidToken => "invalid_token",
+            AuthError::TokenExpired => "token_expired",
+            AuthError::InvalidSignature => "invalid_signature",
+            AuthError::LinkExpired => "link_expired",
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::ServerMisconfigured => write!(f, "Server configuration error"),
+            AuthError::TokenRequired => write!(f, "Token required"),
+            AuthError::InvalidToken => write!(f, "Invalid token"),
+            AuthError::TokenExpired => write!(f, "Token expired"),
+            AuthError::InvalidSignature => write!(f, "Invalid signature"),
+            AuthError::LinkExpired => write!(f, "Link expired"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// 是否启用了 token 认证
+pub fn is_auth_enabled(app_config: &AppConfig) -> bool {
+    app_config.auth.as_ref().is_some_and(|auth| auth.enabled)
+}
+
+/// 校验请求携带的 token
+///
+/// 未配置认证或认证被禁用时总是通过。匹配到 `[[auth.tokens]]` 中的条目时返回该条目，
+/// 使用共享的 `token` 或未启用认证时返回 `None`。
+pub fn validate_token<'a>(
+    app_config: &'a AppConfig,
+    provided: Option<&str>,
+) -> Result<Option<&'a TokenEntry>, AuthError> {
+    let Some(auth_config) = app_config.auth.as_ref().filter(|auth| auth.enabled) else {
+        return Ok(None);
+    };
+
+    let shared = auth_config.token.as_deref().unwrap_or("");
+    if shared.is_empty() && auth_config.tokens.is_empty() && auth_config.signing_key.is_none() {
+        tracing::error!("Authentication is enabled but no token is configured");
+        return Err(AuthError::ServerMisconfigured);
+    }
+
+    let provided = provided.unwrap_or("");
+    if provided.is_empty() {
+        return Err(AuthError::TokenRequired);
+    }
+
+    // 校验过的 token 直接找到对应条目，不必对每个 argon2 哈希重新计算；
+    // 配置重载后条目已不存在时按未命中处理
+    let cached = cached_token(provided);
+    let cached_shared = !shared.is_empty() && cached.as_deref() == Some(shared);
+    let cached_entry = cached.as_deref().and_then(|stored| {
+        auth_config
+            .tokens
+            .iter()
+            .find(|entry| entry.token == stored)
+    });
+    let entry = match cached_entry {
+        Some(entry) => Some(entry),
+        None if cached_shared => None,
+        // 匹配到即停止，错误的 token 仍要对每个 argon2 哈希计算一次，
+        // 因此配置检查要求使用 argon2 时启用按 IP 限流
+        None => auth_config
+            .tokens
+            .iter()
+            .find(|entry| verify_token(provided, &entry.token)),
+    };
+    if let Some(entry) = entry {
+        if !entry.enabled {
+            return Err(AuthError::InvalidToken);
+        }
+        if entry
+            .expires_at
+            .is_some_and(|expires_at| unix_now() >= expires_at)
+        {
+            return Err(AuthError::TokenExpired);
+        }
+        return Ok(Some(entry));
+    }
+
+    if cached_shared || (!shared.is_empty() && verify_token(provided, shared)) {
+        return Ok(None);
+    }
+    Err(AuthError::InvalidToken)
+}
+
+/// 校验签名链接
+///
+/// `path` 为请求路径（不含查询参数），`expires` 为链接中的 `exp` 参数。
+/// 未配置认证或认证被禁用时总是通过。
+pub fn validate_signed_link(
+    app_config: &AppConfig,
+    path: &str,
+    expires: Option<&str>,
+    signature: &str,
+) -> Result<(), AuthError> {
+    let Some(auth_config) = app_config.auth.as_ref().filter(|auth| auth.enabled) else {
+        return Ok(());
+    };
+    let Some(key) = auth_config.signing_key.as_deref() else {
+        return Err(AuthError::InvalidSignature);
+    };
+    let Some(expires) = expires.and_then(|exp| exp.parse::<u64>().ok()) else {
+        return Err(AuthError::InvalidSignature);
+    };
+
+    let expected = sign_link(key, path, expires);
+    let valid: bool = expected
+        .as_bytes()
+        .ct_eq(signature.to_ascii_lowercase().as_bytes())
+        .into();
+    if !valid {
+        return Err(AuthError::InvalidSignature);
+    }
+    if unix_now() >= expires {
+        return Err(AuthError::LinkExpired);
+    }
+    Ok(())
+}
+
+/// 计算签名链接的签名
+///
+/// 签名覆盖请求路径（包含配置档名称）和过期时间，结果为十六进制字符串。
+pub fn sign_link(key: &str, path: &str, expires: u64) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(expires.to_string().as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+/// 从 `Authorization` 请求头中解析 Bearer token
+pub fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+/// token 哈希算法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TokenHashScheme {
+    /// argon2id，PHC 字符串格式
+    #[default]
+    Argon2,
+    /// 加盐的 SHA-256，格式为 `sha256:<salt>:<hex>`
+    Sha256,
+}
+
+impl FromStr for TokenHashScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "argon2" => Ok(TokenHashScheme::Argon2),
+            "sha256" => Ok(TokenHashScheme::Sha256),
+            _ => Err(format!(
+                "unknown hash scheme: {s} (expected argon2 or sha256)"
+            )),
+        }
+    }
+}
+
+/// 计算 token 的哈希，结果可直接写入配置文件中的 token 字段
+pub fn hash_token(token: &str, scheme: TokenHashScheme) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    match scheme {
+        TokenHashScheme::Argon2 => Argon2::default()
+            .hash_password(token.as_bytes(), &salt)
+            .expect("failed to hash token")
+            .to_string(),
+        TokenHashScheme::Sha256 => {
+            let salt = salt.as_str();
+            format!("{SHA256_PREFIX}{salt}:{}", salted_sha256(salt, token))
+        }
+    }
+}
+
+/// 查找已在使用 `token` 的条目，返回条目名称，共享 token 返回 `auth.token`
+///
+/// 同一个 token 每次哈希的盐不同，配置检查只能发现重复的明文或哈希字符串，
+/// 生成哈希时用它检查明文是否已被使用。
+pub fn token_owner<'a>(app_config: &'a AppConfig, token: &str) -> Option<&'a str> {
+    let auth_config = app_config.auth.as_ref()?;
+    if let Some(entry) = auth_config
+        .tokens
+        .iter()
+        .find(|entry| !entry.token.is_empty() && verify_token(token, &entry.token))
+    {
+        return Some(&entry.label);
+    }
+    auth_config
+        .token
+        .as_deref()
+        .filter(|shared| !shared.is_empty() && verify_token(token, shared))
+        .map(|_| "auth.token")
+}
+
+/// 检查配置中的 token 是否为可识别的格式
+///
+/// 以 `sha256:` 或 `$argon2` 开头的值按哈希解析，其他值视为明文 token。
+pub fn check_stored_token(stored: &str) -> Result<(), String> {
+    if let Some(rest) = stored.strip_prefix(SHA256_PREFIX) {
+        let valid = rest.split_once(':').is_some_and(|(salt, digest)| {
+            !salt.is_empty() && digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit())
+        });
+        if !valid {
+            return Err("Invalid sha256 token hash, expected sha256:<salt>:<hex>".to_string());
+        }
+    } else if stored.starts_with(ARGON2_PREFIX) {
+        let hash =
+            PasswordHash::new(stored).map_err(|e| format!("Invalid argon2 token hash: {e}"))?;
+        if hash.hash.is_none() {
+            return Err("Invalid argon2 token hash: missing hash output".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// 配置中的 token 是否为 argon2 哈希
+pub(crate) fn is_argon2_hash(stored: &str) -> bool {
+    stored.starts_with(ARGON2_PREFIX)
+}
+
+/// 校验请求中的 token 是否与配置中的 token（明文或哈希）一致
+fn verify_token(provided: &str, stored: &str) -> bool {
+    if let Some(rest) = stored.strip_prefix(SHA256_PREFIX) {
+        let Some((salt, expected)) = rest.split_once(':') else {
+            return false;
+        };
+        let actual = salted_sha256(salt, provided);
+        return actual
+            .as_bytes()
+            .ct_eq(expected.to_ascii_lowercase().as_bytes())
+            .into();
+    }
+    if stored.starts_with(ARGON2_PREFIX) {
+        let verified = PasswordHash::new(stored).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(provided.as_bytes(), &hash)
+                .is_ok()
+        });
+        if verified {
+            cache_token(provided, stored);
+        }
+        return verified;
+    }
+    tokens_match(provided, stored)
+}
+
+/// 通过 argon2 校验的 token 的摘要到配置中哈希的映射，不保存明文
+fn verified_tokens() -> &'static Mutex<HashMap<[u8; 32], String>> {
+    static VERIFIED: OnceLock<Mutex<HashMap<[u8; 32], String>>> = OnceLock::new();
+    VERIFIED.get_or_init(Default::default)
+}
+
+/// 之前校验通过时匹配的哈希
+fn cached_token(provided: &str) -> Option<String> {
+    let key: [u8; 32] = Sha256::digest(provided.as_bytes()).into();
+    let verified = verified_tokens().lock().unwrap();
+    verified.get(&key).cloned()
+}
+
+/// 记录校验通过的 token，缓存满时清空
+fn cache_token(provided: &str, stored: &str) {
+    let key: [u8; 32] = Sha256::digest(provided.as_bytes()).into();
+    let mut verified = verified_tokens().lock().unwrap();
+    if verified.len() >= VERIFIED_TOKEN_CAPACITY && !verified.contains_key(&key) {
+        verified.clear();
+    }
+    verified.insert(key, stored.to_string());
+}
+
+fn salted_sha256(salt: &str, token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(token.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// 以常数时间比较两个 token
+///
+/// 先计算摘要再比较，避免通过耗时推断出 token 的长度。
+fn tokens_match(provided: &str, expected: &str) -> bool {
+    let provided = Sha256::digest(provided.as_bytes());
+    let expected = Sha256::digest(expected.as_bytes());
+    provided.ct_eq(&expected).into()
+}
+
+/// 当前 unix 时间戳（秒）
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuthConfig;
+
+    fn entry(label: &str, token: &str) -> TokenEntry {
+        TokenEntry {
+            label: label.to_string(),
+            token: token.to_string(),
+            enabled: true,
+            expires_at: None,
+            profile: None,
+            overrides: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_token_entries() {
+        let app_config = AppConfig {
+            auth: Some(AuthConfig {
+                enabled: true,
+                token: None,
+                tokens: vec![
+                    entry("alice", "alice-token"),
+                    TokenEntry {
+                        enabled: false,
+                        ..entry("bob", "bob-token")
+                    },
+                    TokenEntry {
+                        expires_at: Some(1),
+                        ..entry("carol", "carol-token")
+                    },
+                    TokenEntry {
+                        expires_at: Some(u64::MAX),
+                        ..entry("dave", "dave-token")
+                    },
+                ],
+                signing_key: None,
+            }),
+            ..Default::default()
+        };
+
+        let alice = validate_token(&app_config, Some("alice-token")).unwrap();
+        assert_eq!(alice.map(|e| e.label.as_str()), Some("alice"));
+        let dave = validate_token(&app_config, Some("dave-token")).unwrap();
+        assert_eq!(dave.map(|e| e.label.as_str()), Some("dave"));
+
+        assert_eq!(
+            validate_token(&app_config, Some("bob-token")),
+            Err(AuthError::InvalidToken)
+        );
+        assert_eq!(
+            validate_token(&app_config, Some("carol-token")),
+            Err(AuthError::TokenExpired)
+        );
+        assert_eq!(
+            validate_token(&app_config, Some("unknown")),
+            Err(AuthError::InvalidToken)
+        );
+    }
+
+    #[test]
+    fn test_validate_token_shared_and_entries() {
+        let app_config = AppConfig {
+            auth: Some(AuthConfig {
+                enabled: true,
+                token: Some("shared".to_string()),
+                tokens: vec![entry("alice", "alice-token")],
+                signing_key: None,
+            }),
+            ..Default::default()
+        };
+
+        // 共享 token 不对应任何条目
+        assert_eq!(validate_token(&app_config, Some("shared")), Ok(None));
+        assert!(
+            validate_token(&app_config, Some("alice-token"))
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_hashed_tokens() {
+        for scheme in [TokenHashScheme::Argon2, TokenHashScheme::Sha256] {
+            let hash = hash_token("secret", scheme);
+            assert!(check_stored_token(&hash).is_ok());
+            assert!(verify_token("secret", &hash));
+            assert!(!verify_token("Secret", &hash));
+            // 每次使用不同的盐
+            assert_ne!(hash, hash_token("secret", scheme));
+        }
+
+        let hash = format!("sha256:salt:{}", salted_sha256("salt", "secret"));
+        assert!(verify_token("secret", &hash));
+        let upper = format!(
+            "sha256:salt:{}",
+            salted_sha256("salt", "secret").to_uppercase()
+        );
+        assert!(verify_token("secret", &upper));
+
+        // 明文 token 仍然可用
+        assert!(check_stored_token("plain-token").is_ok());
+        assert!(verify_token("plain-token", "plain-token"));
+    }
+
+    #[test]
+    fn test_check_stored_token() {
+        assert!(check_stored_token("sha256:salt").is_err());
+        assert!(check_stored_token("sha256::abcd").is_err());
+        assert!(check_stored_token(&format!("sha256:salt:{}", "z".repeat(64))).is_err());
+        assert!(check_stored_token("$argon2id$garbage").is_err());
+        assert!(!verify_token("secret", "sha256:salt"));
+    }
+
+    #[test]
+    fn test_validate_token_with_hash() {
+        let app_config = AppConfig {
+            auth: Some(AuthConfig {
+                enabled: true,
+                token: Some(hash_token("shared", TokenHashScheme::Sha256)),
+                tokens: vec![entry(
+                    "alice",
+                    &hash_token("alice-token", TokenHashScheme::Argon2),
+                )],
+                signing_key: None,
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(validate_token(&app_config, Some("shared")), Ok(None));
+        let alice = validate_token(&app_config, Some("alice-token")).unwrap();
+        assert_eq!(alice.map(|e| e.label.as_str()), Some("alice"));
+        assert_eq!(
+            validate_token(&app_config, Some("wrong")),
+            Err(AuthError::InvalidToken)
+        );
+    }
+
+    #[test]
+    fn test_token_owner() {
+        let app_config = AppConfig {
+            auth: Some(AuthConfig {
+                enabled: true,
+                token: Some(hash_token("shared", TokenHashScheme::Sha256)),
+                tokens: vec![
+                    entry("alice", &hash_token("alice-token", TokenHashScheme::Sha256)),
+                    entry("bob", "bob-token"),
+                ],
+                signing_key: None,
+            }),
+            ..Default::default()
+        };
+
+        // 重新哈希后字符串不同，仍能发现明文相同
+        assert_eq!(token_owner(&app_config, "alice-token"), Some("alice"));
+        assert_eq!(token_owner(&app_config, "bob-token"), Some("bob"));
+        assert_eq!(token_owner(&app_config, "shared"), Some("auth.token"));
+        assert_eq!(token_owner(&app_config, "new-token"), None);
+        assert_eq!(token_owner(&AppConfig::default(), "new-token"), None);
+    }
+
+    #[test]
+    fn test_validate_token_caches_argon2_matches() {
+        let hash = hash_token("cached-token", TokenHashScheme::Argon2);
+        let mut app_config = AppConfig {
+            auth: Some(AuthConfig {
+                enabled: true,
+                token: None,
+                tokens: vec![entry("erin", &hash)],
+                signing_key: None,
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(cached_token("cached-token"), None);
+        let erin = validate_token(&app_config, Some("cached-token")).unwrap();
+        assert_eq!(erin.map(|e| e.label.as_str()), Some("erin"));
+        assert_eq!(cached_token("cached-token"), Some(hash.clone()));
+        let erin = validate_token(&app_config, Some("cached-token")).unwrap();
+        assert_eq!(erin.map(|e| e.label.as_str()), Some("erin"));
+
+        // 条目被删除后缓存不再生效
+        app_config.auth.as_mut().unwrap().tokens = vec![entry("frank", "frank-token")];
+        assert_eq!(
+            validate_token(&app_config, Some("cached-token")),
+            Err(AuthError::InvalidToken)
+        );
+
+        // 共享 token 同样走缓存
+        app_config.auth.as_mut().unwrap().token = Some(hash);
+        assert_eq!(validate_token(&app_config, Some("cached-token")), Ok(None));
+    }
+
+    #[test]
+    fn test_validate_signed_link() {
+        let app_config = AppConfig {
+            auth: Some(AuthConfig {
+                enabled: true,
+                token: None,
+                tokens: Vec::new(),
+                signing_key: Some("0123456789abcdef".to_string()),
+            }),
+            ..Default::default()
+        };
+        let expires = unix_now() + 3600;
+        let signature = sign_link("0123456789abcdef", "/subs/phone", expires);
+        let exp = expires.to_string();
+
+        assert!(validate_signed_link(&app_config, "/subs/phone", Some(&exp), &signature).is_ok());
+        // 只配置了签名密钥时，普通 token 请求仍然需要 token
+        assert_eq!(
+            validate_token(&app_config, None),
+            Err(AuthError::TokenRequired)
+        );
+
+        // 篡改路径、过期时间或签名
+        assert_eq!(
+            validate_signed_link(&app_config, "/subs", Some(&exp), &signature),
+            Err(AuthError::InvalidSignature)
+        );
+        let later = (expires + 1).to_string();
+        assert_eq!(
+            validate_signed_link(&app_config, "/subs/phone", Some(&later), &signature),
+            Err(AuthError::InvalidSignature)
+        );
+        assert_eq!(
+            validate_signed_link(&app_config, "/subs/phone", Some(&exp), "00"),
+            Err(AuthError::InvalidSignature)
+        );
+        assert_eq!(
+            validate_signed_link(&app_config, "/subs/phone", None, &signature),
+            Err(AuthError::InvalidSignature)
+        );
+
+        // 已过期
+        let signature = sign_link("0123456789abcdef", "/subs", 1);
+        assert_eq!(
+            validate_signed_link(&app_config, "/subs", Some("1"), &signature),
+            Err(AuthError::LinkExpired)
+        );
+
+        // 未配置密钥时不接受签名链接
+        let without_key = AppConfig {
+            auth: Some(AuthConfig {
+                signing_key: None,
+                token: Some("token".to_string()),
+                ..app_config.auth.clone().unwrap()
+            }),
+            ..Default::default()
+        };
+        let signature = sign_link("0123456789abcdef", "/subs", expires);
+        assert_eq!(
+            validate_signed_link(&without_key, "/subs", Some(&exp), &signature),
+            Err(AuthError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("bearer  abc "), Some("abc"));
+        assert_eq!(bearer_token("Basic abc"), None);
+        assert_eq!(bearer_token("Bearer "), None);
+        assert_eq!(bearer_token("abc"), None);
+    }
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secret", "Secret"));
+        assert!(!tokens_match("secret", "secret-longer"));
+    }
+}
@@ -0,0 +1,1186 @@
+use std::{
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::Deserialize;
+use toml::{
+    Spanned,
+    de::{DeTable, DeValue},
+};
+
+use crate::{
+    AppConfig, ConfigFormat, Diagnostics, SourceLocation,
+    app_config::Error,
+    config_store::file_modified,
+    interpolate::{interpolate_json, interpolate_table},
+};
+
+const INCLUDE_KEY: &str = "include";
+
+/// 合并时依次追加的数组，`*` 匹配任意一个键，其他数组整体替换
+const APPENDED_ARRAYS: &[&[&str]] = &[
+    &["groups"],
+    &["rules"],
+    &["auth", "tokens"],
+    &["region-groups", "regions"],
+    &["profiles", "*", "groups"],
+    &["profiles", "*", "rules"],
+    &["profiles", "*", "region-groups", "regions"],
+];
+
+/// 加载时读取过的文件和目录及其修改时间，任一变化都需要重新加载
+pub(crate) type WatchedPaths = Vec<(PathBuf, Option<SystemTime>)>;
+
+/// 加载配置文件，并按 `include` 合并引用的文件
+///
+/// `format` 为 `None` 时按扩展名识别格式，无法识别时按 TOML 解析；
+/// 被引用的文件按各自的扩展名识别，无法识别时沿用引用它的文件的格式。
+/// 即使加载失败也会返回已经读取的文件，以便在它们变化后重试。
+pub(crate) fn load_config(
+    path: &Path,
+    format: Option<ConfigFormat>,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> (Result<AppConfig, Error>, WatchedPaths, SourceMap) {
+    let format = format
+        .or_else(|| ConfigFormat::from_path(path))
+        .unwrap_or_default();
+    let mut sources = ConfigSources::default();
+    let result = sources
+        .visit(path, format, &mut Vec::new())
+        .and_then(|()| sources.deserialize(env));
+    (result, sources.watched, SourceMap(sources.files))
+}
+
+/// 从进程环境变量中读取插值引用的变量
+pub(crate) fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+#[derive(Debug)]
+struct SourceFile {
+    path: PathBuf,
+    canonical: PathBuf,
+    format: ConfigFormat,
+    text: String,
+}
+
+#[derive(Default)]
+struct ConfigSources {
+    /// 按合并顺序排列：被引用的文件在前，根文件在最后
+    files: Vec<SourceFile>,
+    watched: WatchedPaths,
+}
+
+impl ConfigSources {
+    fn visit(
+        &mut self,
+        path: &Path,
+        format: ConfigFormat,
+        stack: &mut Vec<(PathBuf, PathBuf)>,
+    ) -> Result<(), Error> {
+        let is_root = stack.is_empty();
+        let in_file = |error: Error| {
+            if is_root {
+                error
+            } else {
+                Error::Include {
+                    path: path.to_path_buf(),
+                    error: Box::new(error),
+                }
+            }
+        };
+
+        self.watched.push((path.to_path_buf(), file_modified(path)));
+        let canonical = fs::canonicalize(path).map_err(|err| in_file(err.into()))?;
+        if let Some(start) = stack.iter().position(|(seen, _)| *seen == canonical) {
+            let mut cycle: Vec<PathBuf> = stack[start..].iter().map(|(_, p)| p.clone()).collect();
+            cycle.push(path.to_path_buf());
+            return Err(Error::IncludeCycle(cycle));
+        }
+        // 同一个文件被多处引用时只合并一次
+        if self.files.iter().any(|file| file.canonical == canonical) {
+            return Ok(());
+        }
+
+        let text = fs::read_to_string(path).map_err(|err| in_file(err.into()))?;
+        let includes = include_patterns(&text, format, path).map_err(in_file)?;
+
+        stack.push((canonical.clone(), path.to_path_buf()));
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        for pattern in includes {
+            let matched = self
+                .expand(base_dir, &pattern)
+                .map_err(|err| in_file(err.into()))?;
+            for included in matched {
+                let included_format = ConfigFormat::from_path(&included).unwrap_or(format);
+                self.visit(&included, included_format, stack)?;
+            }
+        }
+        stack.pop();
+
+        self.files.push(SourceFile {
+            path: path.to_path_buf(),
+            canonical,
+            format,
+            text,
+        });
+        Ok(())
+    }
+
+    /// 展开 include 中的通配符，结果按路径排序
+    ///
+    /// 支持 glob 语法，没有通配符的路径原样返回，文件不存在时由读取报错。
+    fn expand(&mut self, base_dir: &Path, pattern: &str) -> io::Result<Vec<PathBuf>> {
+        let path = base_dir.join(pattern);
+        if !has_wildcard(pattern) {
+            return Ok(vec![path]);
+        }
+
+        // 基准目录中的特殊字符按字面匹配
+        let full = Path::new(&glob::Pattern::escape(&base_dir.to_string_lossy())).join(pattern);
+        let options = glob::MatchOptions {
+            // 与 shell 一致，通配符不匹配隐藏文件
+            require_literal_leading_dot: true,
+            ..Default::default()
+        };
+        let entries = glob::glob_with(&full.to_string_lossy(), options).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid pattern '{pattern}': {err}"),
+            )
+        })?;
+        let mut matched = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(io::Error::from)?;
+            if entry.is_file() {
+                matched.push(entry);
+            }
+        }
+        matched.sort();
+
+        // 目录的修改时间在增删文件时变化
+        let prefix: PathBuf = path
+            .components()
+            .take_while(|component| !has_wildcard(&component.as_os_str().to_string_lossy()))
+            .collect();
+        let mut dirs = vec![prefix];
+        dirs.extend(
+            matched
+                .iter()
+                .filter_map(|file| file.parent().map(Path::to_path_buf)),
+        );
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            let dir = if dir.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                dir
+            };
+            let modified = file_modified(&dir);
+            self.watched.push((dir, modified));
+        }
+        Ok(matched)
+    }
+
+    fn deserialize(&self, env: &dyn Fn(&str) -> Option<String>) -> Result<AppConfig, Error> {
+        if self
+            .files
+            .iter()
+            .all(|file| file.format == ConfigFormat::Toml)
+        {
+            return self.deserialize_toml(env);
+        }
+
+        // 含有 YAML 或 JSON 文件时统一转换为 JSON 值再合并，出错时失去位置信息
+        let root = self.files.len() - 1;
+        let mut merged = serde_json::Value::Object(serde_json::Map::new());
+        for (index, file) in self.files.iter().enumerate() {
+            let mut value =
+                parse_value(&file.text, file.format).map_err(|err| self.in_file(index, err))?;
+            let base_dir = file.path.parent().unwrap_or(Path::new(""));
+            interpolate_json(&mut value, base_dir, env).map_err(|err| self.in_file(index, err))?;
+            if index != root
+                && let Some(object) = value.as_object_mut()
+            {
+                object.remove(INCLUDE_KEY);
+            }
+            merge_json(&mut merged, value);
+        }
+
+        serde_path_to_error::deserialize(merged).map_err(|err| {
+            // 单独解析各个文件，找到报告同一错误的文件和其中的行号
+            let message = err.inner().to_string();
+            for (index, file) in self.files.iter().enumerate() {
+                if let Err(located) = check_file(file)
+                    && located.to_string().contains(&message)
+                {
+                    return self.in_file(index, located);
+                }
+            }
+            Error::Invalid {
+                key: err.path().to_string(),
+                message,
+            }
+        })
+    }
+
+    fn deserialize_toml(&self, env: &dyn Fn(&str) -> Option<String>) -> Result<AppConfig, Error> {
+        let root = self.files.len() - 1;
+        let table = self.assemble(root, env)?;
+        let mut err = match AppConfig::deserialize(toml::de::Deserializer::from(table)) {
+            Ok(config) => return Ok(config),
+            Err(err) => err,
+        };
+
+        // 根据出错位置找到对应的文件
+        let file = match err.span() {
+            Some(span) => self.file_at(root, span.start),
+            None => root,
+        };
+        if file == root {
+            err.set_input(Some(&self.files[root].text));
+            return Err(Error::Toml(err));
+        }
+
+        // 把出错的文件放在偏移 0 处重新反序列化，得到该文件内的位置
+        let relocated = self.assemble(file, env).and_then(|table| {
+            AppConfig::deserialize(toml::de::Deserializer::from(table)).map_err(Error::Toml)
+        });
+        let error = match relocated {
+            Err(Error::Toml(mut relocated)) => {
+                relocated.set_input(Some(&self.files[file].text));
+                relocated
+            }
+            _ => err,
+        };
+        Err(self.in_file(file, Error::Toml(error)))
+    }
+
+    /// 被引用的文件中的错误附带文件路径，根文件中的错误原样返回
+    fn in_file(&self, index: usize, error: Error) -> Error {
+        if index == self.files.len() - 1 {
+            error
+        } else {
+            Error::Include {
+                path: self.files[index].path.clone(),
+                error: Box::new(error),
+            }
+        }
+    }
+
+    /// 合并所有文件
+    ///
+    /// 各文件的位置信息互不重叠：`first` 从 0 开始，其余文件依次排在后面。
+    fn assemble(
+        &self,
+        first: usize,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Spanned<DeTable<'_>>, Error> {
+        let root = self.files.len() - 1;
+        let offsets = self.offsets(first);
+        let mut merged = DeTable::new();
+        let mut root_span = 0..0;
+
+        for (index, file) in self.files.iter().enumerate() {
+            let in_file = |error: Error| self.in_file(index, error);
+            let parsed = DeTable::parse(&file.text).map_err(|err| in_file(err.into()))?;
+            let span = parsed.span();
+            let mut table = shift_table(parsed.into_inner(), offsets[index]);
+            let base_dir = file.path.parent().unwrap_or(Path::new(""));
+            interpolate_table(&mut table, base_dir, env).map_err(in_file)?;
+
+            if index == root {
+                root_span = span.start + offsets[index]..span.end + offsets[index];
+            } else {
+                table.remove(INCLUDE_KEY);
+            }
+            merge_table(&mut merged, table);
+        }
+
+        Ok(Spanned::new(root_span, merged))
+    }
+
+    fn offsets(&self, first: usize) -> Vec<usize> {
+        let mut offsets = vec![0; self.files.len()];
+        let mut next = self.files[first].text.len() + 1;
+        for (index, file) in self.files.iter().enumerate() {
+            if index != first {
+                offsets[index] = next;
+                next += file.text.len() + 1;
+            }
+        }
+        offsets
+    }
+
+    fn file_at(&self, first: usize, position: usize) -> usize {
+        let offsets = self.offsets(first);
+        (0..self.files.len())
+            .find(|&index| {
+                (offsets[index]..=offsets[index] + self.files[index].text.len()).contains(&position)
+            })
+            .unwrap_or(first)
+    }
+}
+
+/// 合并的各个配置文件，用于把键路径定位到文件中的行列号
+#[derive(Debug, Default)]
+pub(crate) struct SourceMap(Vec<SourceFile>);
+
+impl SourceMap {
+    /// 为问题填上所在的文件和行列号
+    ///
+    /// 只有 TOML 文件带有位置信息，YAML 和 JSON 文件只参与数组下标的计算。
+    pub(crate) fn locate(&self, diagnostics: &mut Diagnostics) {
+        let roots: Vec<(usize, Node)> = self
+            .0
+            .iter()
+            .enumerate()
+            .filter_map(|(index, file)| Some((index, Node::parse(file)?)))
+            .collect();
+        for diagnostic in diagnostics.iter_mut() {
+            let Some(segments) = parse_key_path(&diagnostic.path) else {
+                continue;
+            };
+            let candidates = roots.iter().map(|(index, root)| (*index, root)).collect();
+            if let Some((index, node)) = resolve(candidates, &segments, 0)
+                && let Some(span) = &node.span
+            {
+                let file = &self.0[index];
+                let (line, column) = line_column(&file.text, span.start);
+                diagnostic.location = Some(SourceLocation {
+                    file: file.path.clone(),
+                    line,
+                    column,
+                });
+            }
+        }
+    }
+}
+
+/// 带位置的配置树
+struct Node {
+    span: Option<Range<usize>>,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    Table(Vec<(String, Node)>),
+    Array(Vec<Node>),
+    Scalar,
+}
+
+impl Node {
+    fn parse(file: &SourceFile) -> Option<Node> {
+        if file.format != ConfigFormat::Toml {
+            return Some(Node::from_json(&parse_value(&file.text, file.format).ok()?));
+        }
+        let root = DeTable::parse(&file.text).ok()?;
+        Some(Node {
+            span: Some(root.span()),
+            kind: NodeKind::Table(Node::from_table(root.get_ref())),
+        })
+    }
+
+    fn from_table(table: &DeTable<'_>) -> Vec<(String, Node)> {
+        table
+            .iter()
+            .map(|(key, value)| (key.get_ref().to_string(), Node::from_toml(value)))
+            .collect()
+    }
+
+    fn from_toml(value: &Spanned<DeValue<'_>>) -> Node {
+        let kind = match value.get_ref() {
+            DeValue::Table(table) => NodeKind::Table(Node::from_table(table)),
+            DeValue::Array(array) => NodeKind::Array(array.iter().map(Node::from_toml).collect()),
+            _ => NodeKind::Scalar,
+        };
+        Node {
+            span: Some(value.span()),
+            kind,
+        }
+    }
+
+    fn from_json(value: &serde_json::Value) -> Node {
+        let kind = match value {
+            serde_json::Value::Object(object) => NodeKind::Table(
+                object
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Node::from_json(value)))
+                    .collect(),
+            ),
+            serde_json::Value::Array(array) => {
+                NodeKind::Array(array.iter().map(Node::from_json).collect())
+            }
+            _ => NodeKind::Scalar,
+        };
+        Node { span: None, kind }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// 解析 `rules[12].target`、`proxies."my.sub"` 形式的键路径
+fn parse_key_path(path: &str) -> Option<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut chars = path.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '.' => {
+                chars.next();
+            }
+            '[' => {
+                chars.next();
+                let digits: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                segments.push(Segment::Index(digits.parse().ok()?));
+            }
+            '"' => {
+                chars.next();
+                let mut key = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => key.push(match chars.next()? {
+                            'n' => '\n',
+                            't' => '\t',
+                            'r' => '\r',
+                            '0' => '\0',
+                            other => other,
+                        }),
+                        other => key.push(other),
+                    }
+                }
+                segments.push(Segment::Key(key));
+            }
+            _ => {
+                let mut key = String::new();
+                while let Some(&c) = chars.peek().filter(|&&c| c != '.' && c != '[') {
+                    key.push(c);
+                    chars.next();
+                }
+                segments.push(Segment::Key(key));
+            }
+        }
+    }
+    Some(segments)
+}
+
+/// 按合并规则在各文件中查找键路径对应的值
+///
+/// 同一个值以最后定义它的文件为准；[`APPENDED_ARRAYS`] 中的数组依次追加，下标跨文件计算。
+/// 完整路径找不到时（如使用了默认值）退回到最深的上级。
+fn resolve<'a>(
+    candidates: Vec<(usize, &'a Node)>,
+    path: &[Segment],
+    depth: usize,
+) -> Option<(usize, &'a Node)> {
+    let fallback = || candidates.last().copied().filter(|_| depth > 0);
+    let Some(segment) = path.get(depth) else {
+        return fallback();
+    };
+
+    let next: Vec<(usize, &Node)> = match segment {
+        Segment::Key(key) => candidates
+            .iter()
+            .filter_map(|(file, node)| match &node.kind {
+                NodeKind::Table(entries) => entries
+                    .iter()
+                    .find(|(name, _)| name == key)
+                    .map(|(_, value)| (*file, value)),
+                _ => None,
+            })
+            .collect(),
+        Segment::Index(index) => {
+            let arrays: Vec<(usize, &Vec<Node>)> = candidates
+                .iter()
+                .filter_map(|(file, node)| match &node.kind {
+                    NodeKind::Array(items) => Some((*file, items)),
+                    _ => None,
+                })
+                .collect();
+            let keys: Option<Vec<&str>> = path[..depth]
+                .iter()
+                .map(|segment| match segment {
+                    Segment::Key(key) => Some(key.as_str()),
+                    Segment::Index(_) => None,
+                })
+                .collect();
+            let appended = keys.is_some_and(|keys| is_appended(&keys));
+            let items: Vec<(usize, &Node)> = if appended {
+                arrays
+                    .iter()
+                    .flat_map(|(file, items)| items.iter().map(|item| (*file, item)))
+                    .collect()
+            } else {
+                arrays
+                    .last()
+                    .map(|(file, items)| items.iter().map(|item| (*file, item)).collect())
+                    .unwrap_or_default()
+            };
+            items.get(*index).copied().into_iter().collect()
+        }
+    };
+
+    if next.is_empty() {
+        return fallback();
+    }
+    resolve(next, path, depth + 1)
+}
+
+/// 字节偏移对应的行号和列号，从 1 开始
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// 读取文件中的 `include` 列表
+fn include_patterns(text: &str, format: ConfigFormat, path: &Path) -> Result<Vec<String>, Error> {
+    let invalid = || Error::InvalidInclude {
+        path: path.to_path_buf(),
+        message: "`include` must be an array of file paths".to_string(),
+    };
+    if format != ConfigFormat::Toml {
+        let value = parse_value(text, format)?;
+        let Some(value) = value.get(INCLUDE_KEY) else {
+            return Ok(Vec::new());
+        };
+        let Some(items) = value.as_array() else {
+            return Err(invalid());
+        };
+        return items
+            .iter()
+            .map(|item| item.as_str().map(str::to_string).ok_or_else(invalid))
+            .collect();
+    }
+
+    let table = DeTable::parse(text)?;
+    let Some(value) = table.get_ref().get(INCLUDE_KEY) else {
+        return Ok(Vec::new());
+    };
+    let DeValue::Array(items) = value.get_ref() else {
+        return Err(invalid());
+    };
+    items
+        .iter()
+        .map(|item| match item.get_ref() {
+            DeValue::String(pattern) => Ok(pattern.to_string()),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+/// 把任意格式的文件解析为 JSON 值，空文件视为空表
+fn parse_value(text: &str, format: ConfigFormat) -> Result<serde_json::Value, Error> {
+    let value = match format {
+        ConfigFormat::Toml => toml::from_str(text)?,
+        ConfigFormat::Yaml => serde_yaml::from_str(text)?,
+        ConfigFormat::Json => serde_json::from_str(text)?,
+    };
+    Ok(match value {
+        serde_json::Value::Null => serde_json::Value::Object(serde_json::Map::new()),
+        value => value,
+    })
+}
+
+/// 按文件自身的格式解析，错误信息带有行号
+fn check_file(file: &SourceFile) -> Result<(), Error> {
+    match file.format {
+        ConfigFormat::Toml => drop(toml::from_str::<AppConfig>(&file.text)?),
+        // 空的 YAML 文件解析为 `None`
+        ConfigFormat::Yaml => drop(serde_yaml::from_str::<Option<AppConfig>>(&file.text)?),
+        ConfigFormat::Json => drop(serde_json::from_str::<AppConfig>(&file.text)?),
+    }
+    Ok(())
+}
+
+/// 该路径上的数组是否在合并时追加，见 [`APPENDED_ARRAYS`]
+fn is_appended<K: AsRef<str>>(path: &[K]) -> bool {
+    APPENDED_ARRAYS.iter().any(|pattern| {
+        pattern.len() == path.len()
+            && pattern
+                .iter()
+                .zip(path)
+                .all(|(expected, key)| *expected == "*" || *expected == key.as_ref())
+    })
+}
+
+/// 与 [`merge_table`] 相同的规则合并 JSON 值
+fn merge_json(base: &mut serde_json::Value, overlay: serde_json::Value) {
+    merge_json_at(base, overlay, &mut Vec::new());
+}
+
+fn merge_json_at(base: &mut serde_json::Value, overlay: serde_json::Value, path: &mut Vec<String>) {
+    use serde_json::Value;
+
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => {
+                        path.push(key);
+                        merge_json_at(existing, value, path);
+                        path.pop();
+                    }
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(overlay)) if is_appended(path) => base.extend(overlay),
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// 合并两个表：同名的表递归合并，[`APPENDED_ARRAYS`] 中的数组（如 `[[rules]]`）追加在后面，
+/// 其他值被替换
+fn merge_table<'i>(base: &mut DeTable<'i>, overlay: DeTable<'i>) {
+    merge_table_at(base, overlay, &mut Vec::new());
+}
+
+fn merge_table_at<'i>(base: &mut DeTable<'i>, overlay: DeTable<'i>, path: &mut Vec<String>) {
+    for (key, value) in overlay {
+        match base.get_mut(key.get_ref().as_ref()) {
+            Some(existing) => {
+                path.push(key.get_ref().to_string());
+                merge_value(existing, value, path);
+                path.pop();
+            }
+            None => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn merge_value<'i>(
+    base: &mut Spanned<DeValue<'i>>,
+    overlay: Spanned<DeValue<'i>>,
+    path: &mut Vec<String>,
+) {
+    match (base.get_mut(), overlay.get_ref()) {
+        (DeValue::Table(_), DeValue::Table(_)) => {}
+        (DeValue::Array(_), DeValue::Array(_)) if is_appended(path) => {}
+        _ => {
+            *base = overlay;
+            return;
+        }
+    }
+
+    match (base.get_mut(), overlay.into_inner()) {
+        (DeValue::Table(table), DeValue::Table(overlay)) => merge_table_at(table, overlay, path),
+        (DeValue::Array(array), DeValue::Array(overlay)) => {
+            for item in overlay {
+                array.push(item);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn shift_table(table: DeTable<'_>, offset: usize) -> DeTable<'_> {
+    table
+        .into_iter()
+        .map(|(key, value)| (shift_span(key, offset), shift_value(value, offset)))
+        .collect()
+}
+
+fn shift_value(value: Spanned<DeValue<'_>>, offset: usize) -> Spanned<DeValue<'_>> {
+    let value = shift_span(value, offset);
+    let span = value.span();
+    let inner = match value.into_inner() {
+        DeValue::Table(table) => DeValue::Table(shift_table(table, offset)),
+        DeValue::Array(array) => DeValue::Array(
+            array
+                .into_iter()
+                .map(|item| shift_value(item, offset))
+                .collect(),
+        ),
+        inner => inner,
+    };
+    Spanned::new(span, inner)
+}
+
+fn shift_span<T>(value: Spanned<T>, offset: usize) -> Spanned<T> {
+    let span = value.span();
+    Spanned::new(span.start + offset..span.end + offset, value.into_inner())
+}
+
+fn has_wildcard(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProxyGroup, ProxySource, RuleCfg};
+    use tempfile::TempDir;
+
+    fn write(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn load(path: &Path) -> Result<AppConfig, Error> {
+        load_config(path, None, &|_| None).0
+    }
+
+    fn rule_value(rule: &RuleCfg) -> &str {
+        match rule {
+            RuleCfg::Single(rule) => &rule.value,
+            RuleCfg::Set(rule) => &rule.name,
+        }
+    }
+
+    #[test]
+    fn test_include_merges_files() {
+        let dir = TempDir::new().unwrap();
+        write(
+            dir.path(),
+            "rules/common.toml",
+            r#"
+[provider-config]
+update-interval = 7200
+lazy = true
+
+[proxies]
+shared = "https://shared.example.com/sub"
+
+[[rules]]
+type = "single"
+tag = "DOMAIN"
+value = "common.example.com"
+target = "DIRECT"
+"#,
+        );
+        write(
+            dir.path(),
+            "groups/b.toml",
+            "[[groups]]\nname = \"B\"\ntype = \"select\"\nproxies = [\"DIRECT\"]\n",
+        );
+        write(
+            dir.path(),
+            "groups/a.toml",
+            "[[groups]]\nname = \"A\"\ntype = \"select\"\nproxies = [\"DIRECT\"]\n",
+        );
+        write(dir.path(), "groups/.hidden.toml", "invalid [[[");
+        let root = write(
+            dir.path(),
+            "config.toml",
+            r#"
+include = ["rules/common.toml", "groups/*.toml"]
+
+[provider-config]
+lazy = false
+
+[proxies]
+own = "https://own.example.com/sub"
+
+[[rules]]
+type = "single"
+tag = "DOMAIN"
+value = "own.example.com"
+target = "DIRECT"
+"#,
+        );
+
+        let config = load(&root).unwrap();
+        assert_eq!(config.include, vec!["rules/common.toml", "groups/*.toml"]);
+        // 表递归合并，后加载的值覆盖先加载的
+        let provider_config = config.provider_config.unwrap();
+        assert_eq!(provider_config.update_interval, Some(7200));
+        assert_eq!(provider_config.lazy, Some(false));
+        assert_eq!(config.proxies.len(), 2);
+        // 表数组按加载顺序追加，通配符按文件名排序
+        let rules: Vec<_> = config.rules.iter().map(rule_value).collect();
+        assert_eq!(rules, vec!["common.example.com", "own.example.com"]);
+        let groups: Vec<_> = config
+            .groups
+            .iter()
+            .map(|group| match group {
+                ProxyGroup::Select(select) => select.common.name.as_str(),
+                _ => panic!("Expected select group"),
+            })
+            .collect();
+        assert_eq!(groups, vec!["A", "B"]);
+    }
+
+    #[test]
+    fn test_merge_table() {
+        let base = DeTable::parse(
+            r#"
+name = "base"
+list = [1, 2]
+tables = [{ a = 1 }]
+rules = [{ a = 1 }]
+[nested]
+a = 1
+b = 1
+"#,
+        )
+        .unwrap();
+        let overlay = DeTable::parse(
+            r#"
+list = [3]
+tables = [{ a = 2 }]
+rules = [{ a = 2 }]
+[nested]
+b = 2
+c = 2
+"#,
+        )
+        .unwrap();
+
+        let mut merged = base.into_inner();
+        merge_table(&mut merged, overlay.into_inner());
+        let merged: toml::Table =
+            toml::Table::deserialize(toml::de::Deserializer::from(Spanned::new(0..0, merged)))
+                .unwrap();
+        let expected: toml::Table = toml::from_str(
+            r#"
+name = "base"
+list = [3]
+tables = [{ a = 2 }]
+rules = [{ a = 1 }, { a = 2 }]
+nested = { a = 1, b = 2, c = 2 }
+"#,
+        )
+        .unwrap();
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn test_include_replaces_scalar_arrays() {
+        // TOML 文件直接合并，含有 YAML 文件时转换为 JSON 值合并，两者规则一致
+        let includes = [
+            (
+                "common.toml",
+                r#"
+[proxies.a]
+url = "https://a.example.com/sub"
+header = { User-Agent = ["clash", "mihomo"] }
+
+[profiles.router]
+rules = [{ type = "single", tag = "DOMAIN", value = "a.example.com", target = "DIRECT" }]
+"#,
+            ),
+            (
+                "common.yaml",
+                r#"
+proxies:
+  a:
+    url: https://a.example.com/sub
+    header:
+      User-Agent: [clash, mihomo]
+profiles:
+  router:
+    rules:
+      - { type: single, tag: DOMAIN, value: a.example.com, target: DIRECT }
+"#,
+            ),
+        ];
+        for (name, text) in includes {
+            let dir = TempDir::new().unwrap();
+            write(dir.path(), name, text);
+            let root = write(
+                dir.path(),
+                "config.toml",
+                &format!(
+                    r#"
+include = ["{name}"]
+
+[proxies.a]
+url = "https://a.example.com/sub"
+header = {{ User-Agent = ["sub-util"] }}
+
+[[profiles.router.rules]]
+type = "single"
+tag = "DOMAIN"
+value = "b.example.com"
+target = "DIRECT"
+"#
+                ),
+            );
+
+            let config = load(&root).unwrap();
+            // 字符串数组整体替换
+            let ProxySource::Provider(source) = &config.proxies["a"] else {
+                panic!("Expected provider source");
+            };
+            let header = source.header.as_ref().unwrap();
+            assert_eq!(header["User-Agent"], vec!["sub-util"], "{name}");
+            // 声明为追加的表数组依次追加
+            let rules: Vec<_> = config.profiles["router"]
+                .rules
+                .iter()
+                .flatten()
+                .map(rule_value)
+                .collect();
+            assert_eq!(rules, vec!["a.example.com", "b.example.com"], "{name}");
+        }
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let dir = TempDir::new().unwrap();
+        write(dir.path(), "a.toml", "include = [\"b.toml\"]\n");
+        write(dir.path(), "b.toml", "include = [\"a.toml\"]\n");
+        let root = write(dir.path(), "config.toml", "include = [\"a.toml\"]\n");
+
+        let err = load(&root).unwrap_err();
+        let Error::IncludeCycle(paths) = &err else {
+            panic!("Expected include cycle, got {err:?}");
+        };
+        let names: Vec<_> = paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["a.toml", "b.toml", "a.toml"]);
+
+        // 同一文件被多处引用不算循环
+        write(dir.path(), "a.toml", "");
+        write(dir.path(), "b.toml", "include = [\"a.toml\"]\n");
+        let root = write(
+            dir.path(),
+            "config.toml",
+            "include = [\"a.toml\", \"b.toml\"]\n",
+        );
+        assert!(load(&root).is_ok());
+    }
+
+    #[test]
+    fn test_include_errors_name_file() {
+        let dir = TempDir::new().unwrap();
+        let root = write(dir.path(), "config.toml", "include = [\"missing.toml\"]\n");
+        let err = load(&root).unwrap_err();
+        assert!(
+            matches!(err, Error::Include { ref path, .. } if path.ends_with("missing.toml")),
+            "{err:?}"
+        );
+
+        // 语法错误
+        write(dir.path(), "broken.toml", "[proxies\n");
+        write(dir.path(), "config.toml", "include = [\"broken.toml\"]\n");
+        let err = load(&root).unwrap_err();
+        assert!(err.to_string().contains("broken.toml"), "{err}");
+
+        // 类型错误报告所在文件中的行号
+        write(
+            dir.path(),
+            "groups.toml",
+            "# groups\n\n[[groups]]\nname = \"A\"\ntype = \"unknown\"\n",
+        );
+        write(
+            dir.path(),
+            "config.toml",
+            "include = [\"groups.toml\"]\n\n[proxies]\na = \"https://example.com/sub\"\n",
+        );
+        let err = load(&root).unwrap_err();
+        let message = err.to_string();
+        assert!(message.starts_with("in "), "{message}");
+        assert!(message.contains("groups.toml"), "{message}");
+        assert!(message.contains("line 5"), "{message}");
+
+        // 根文件中的错误不附带文件名
+        write(
+            dir.path(),
+            "config.toml",
+            "include = [\"groups.toml\"]\nproxies = 1\n",
+        );
+        write(dir.path(), "groups.toml", "");
+        let err = load(&root).unwrap_err();
+        assert!(matches!(err, Error::Toml(_)));
+        assert!(err.to_string().contains("line 2"), "{err}");
+
+        write(dir.path(), "config.toml", "include = \"groups.toml\"\n");
+        let err = load(&root).unwrap_err();
+        assert!(matches!(err, Error::InvalidInclude { .. }));
+    }
+
+    #[test]
+    fn test_include_watches_files() {
+        let dir = TempDir::new().unwrap();
+        write(dir.path(), "groups/a.toml", "");
+        let root = write(
+            dir.path(),
+            "config.toml",
+            "include = [\"groups/*.toml\", \"missing.toml\"]\n",
+        );
+
+        let (result, watched, _) = load_config(&root, None, &|_| None);
+        assert!(result.is_err());
+        let paths: Vec<_> = watched.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                root.clone(),
+                dir.path().join("groups"),
+                dir.path().join("groups/a.toml"),
+                dir.path().join("missing.toml"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_include_mixed_formats() {
+        let dir = TempDir::new().unwrap();
+        write(
+            dir.path(),
+            "rules.yaml",
+            "rules:\n  - type: single\n    tag: DOMAIN\n    value: ${DOMAIN}\n    target: DIRECT\n",
+        );
+        write(
+            dir.path(),
+            "groups.json",
+            r#"{"groups": [{"name": "A", "type": "select", "proxies": ["DIRECT"]}]}"#,
+        );
+        let root = write(
+            dir.path(),
+            "config.toml",
+            r#"
+include = ["rules.yaml", "groups.json"]
+
+[[rules]]
+type = "single"
+tag = "DOMAIN"
+value = "own.example.com"
+target = "DIRECT"
+"#,
+        );
+
+        let env = |name: &str| (name == "DOMAIN").then(|| "yaml.example.com".to_string());
+        let config = load_config(&root, None, &env).0.unwrap();
+        let rules: Vec<_> = config.rules.iter().map(rule_value).collect();
+        assert_eq!(rules, vec!["yaml.example.com", "own.example.com"]);
+        assert_eq!(config.groups.len(), 1);
+
+        // 错误定位到所在文件及其中的行号
+        write(dir.path(), "groups.json", "{\n  \"groups\": {}\n}");
+        let err = load_config(&root, None, &env).0.unwrap_err();
+        let Error::Include { path, error } = &err else {
+            panic!("Expected include error, got {err:?}");
+        };
+        assert!(path.ends_with("groups.json"));
+        assert!(matches!(**error, Error::Json(_)), "{error:?}");
+        assert!(err.to_string().contains("line 2"), "{err}");
+
+        // 单独不完整的文件合并后有效
+        write(
+            dir.path(),
+            "groups.json",
+            "{\"region-groups\": {\"regions\": []}}",
+        );
+        write(dir.path(), "rules.yaml", "");
+        write(
+            dir.path(),
+            "config.toml",
+            "include = [\"rules.yaml\", \"groups.json\"]\n\n[region-groups]\nenabled = true\n",
+        );
+        assert!(load_config(&root, None, &env).0.is_ok());
+        write(dir.path(), "rules.yaml", "region-groups:\n  enabled: 1\n");
+        write(
+            dir.path(),
+            "config.toml",
+            "include = [\"rules.yaml\", \"groups.json\"]\n",
+        );
+        let err = load_config(&root, None, &env).0.unwrap_err();
+        assert!(matches!(err, Error::Include { ref path, .. } if path.ends_with("rules.yaml")));
+        assert!(err.to_string().contains("line 2"), "{err}");
+    }
+
+    #[test]
+    fn test_locate_diagnostics() {
+        let dir = TempDir::new().unwrap();
+        let root = write(
+            dir.path(),
+            "config.toml",
+            r#"include = ["rules.toml"]
+
+[proxies]
+"my.sub" = "not-a-url"
+
+[[rules]]
+type = "single"
+tag = "MATCH"
+target = "Missing"
+"#,
+        );
+        write(
+            dir.path(),
+            "rules.toml",
+            r#"[[rules]]
+type = "single"
+tag = "DOMAIN"
+value = "example.com"
+target = "DIRECT"
+"#,
+        );
+        let (result, _, sources) = load_config(&root, None, &|_| None);
+        assert_eq!(result.unwrap().rules.len(), 2);
+
+        let mut diagnostics = Diagnostics::default();
+        for path in [
+            r#"proxies."my.sub""#,
+            "rules[0].target",
+            "rules[1].target",
+            // 使用默认值的字段定位到上级
+            "rules[1].value",
+            "server.public-url",
+        ] {
+            diagnostics.warning(path, "test");
+        }
+        sources.locate(&mut diagnostics);
+
+        let locations: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                diagnostic.location.as_ref().map(|location| {
+                    (
+                        location.file.file_name().unwrap().to_str().unwrap(),
+                        location.line,
+                        location.column,
+                    )
+                })
+            })
+            .collect();
+        assert_eq!(
+            locations,
+            vec![
+                Some(("config.toml", 4, 12)),
+                Some(("rules.toml", 5, 10)),
+                Some(("config.toml", 9, 10)),
+                Some(("config.toml", 6, 1)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_key_path() {
+        assert_eq!(
+            parse_key_path(r#"auth.tokens[2]."a\"b".label"#),
+            Some(vec![
+                Segment::Key("auth".to_string()),
+                Segment::Key("tokens".to_string()),
+                Segment::Index(2),
+                Segment::Key("a\"b".to_string()),
+                Segment::Key("label".to_string()),
+            ])
+        );
+        assert_eq!(parse_key_path("rules[x]"), None);
+        assert_eq!(parse_key_path(r#"proxies."open"#), None);
+    }
+}
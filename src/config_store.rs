@@ -0,0 +1,329 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use crate::{
+    AppConfig, ConfigError, ConfigFormat, Diagnostics, app_config, check_app_config,
+    config_loader::{SourceMap, WatchedPaths, env_var, load_config},
+    metrics,
+};
+
+#[derive(Debug)]
+pub enum ReloadError {
+    Load(app_config::Error),
+    Invalid(ConfigError),
+}
+
+impl fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReloadError::Load(err) => write!(f, "failed to load config: {err}"),
+            ReloadError::Invalid(err) => write!(f, "config rejected: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ReloadError {}
+
+impl From<app_config::Error> for ReloadError {
+    fn from(value: app_config::Error) -> Self {
+        Self::Load(value)
+    }
+}
+
+impl From<ConfigError> for ReloadError {
+    fn from(value: ConfigError) -> Self {
+        Self::Invalid(value)
+    }
+}
+
+/// 某一时刻生效的配置快照
+#[derive(Debug)]
+pub struct ConfigSnapshot {
+    pub config: Arc<AppConfig>,
+    pub loaded_at: SystemTime,
+    /// 配置文件及其 include 的文件在加载时的修改时间
+    watched: WatchedPaths,
+    /// 加载的各个文件，用于定位配置问题
+    sources: Arc<SourceMap>,
+    /// 加载时检查配置得到的问题，带有所在文件的行列号
+    diagnostics: Arc<Diagnostics>,
+}
+
+impl ConfigSnapshot {
+    /// 检查配置，问题带有所在文件的行列号，结果在加载时计算
+    pub fn check(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+}
+
+/// 检查配置并定位问题所在的文件和行列号
+fn locate_diagnostics(config: &AppConfig, sources: &SourceMap) -> Diagnostics {
+    let mut diagnostics = check_app_config(config);
+    sources.locate(&mut diagnostics);
+    diagnostics
+}
+
+/// 持有当前生效的配置，支持从文件热重载
+#[derive(Debug)]
+pub struct ConfigStore {
+    path: PathBuf,
+    /// 命令行指定的格式，`None` 表示按扩展名识别
+    format: Option<ConfigFormat>,
+    current: RwLock<Arc<ConfigSnapshot>>,
+}
+
+impl ConfigStore {
+    /// 首次加载配置
+    ///
+    /// 与重载不同，首次加载不要求配置通过校验，以便服务仍能启动并报告错误。
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, app_config::Error> {
+        Self::load_with_format(path, None)
+    }
+
+    /// 以指定格式首次加载配置，之后的重载沿用该格式
+    pub fn load_with_format(
+        path: impl AsRef<Path>,
+        format: Option<ConfigFormat>,
+    ) -> Result<Self, app_config::Error> {
+        let path = path.as_ref().to_path_buf();
+        let (result, watched, sources) = load_config(&path, format, &env_var);
+        let config = result?;
+        metrics().record_config_loaded();
+        let diagnostics = locate_diagnostics(&config, &sources);
+        Ok(Self {
+            path,
+            format,
+            current: RwLock::new(Arc::new(ConfigSnapshot {
+                config: Arc::new(config),
+                loaded_at: SystemTime::now(),
+                watched,
+                sources: Arc::new(sources),
+                diagnostics: Arc::new(diagnostics),
+            })),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 获取当前配置
+    pub fn current(&self) -> Arc<AppConfig> {
+        self.snapshot().config.clone()
+    }
+
+    /// 获取当前配置快照（包含加载时间）
+    pub fn snapshot(&self) -> Arc<ConfigSnapshot> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// 检查当前配置，问题带有所在文件的行列号
+    pub fn check(&self) -> Diagnostics {
+        self.snapshot().check().clone()
+    }
+
+    /// 重新加载配置文件
+    ///
+    /// 新配置只有在通过 `validate_app_config` 后才会替换当前配置，
+    /// 否则保留上一次可用的配置并返回包含所有问题的错误。
+    pub fn reload(&self) -> Result<Arc<AppConfig>, ReloadError> {
+        let (result, watched, sources) = load_config(&self.path, self.format, &env_var);
+        let result = result.map_err(ReloadError::from).and_then(|config| {
+            let diagnostics = locate_diagnostics(&config, &sources).into_result()?;
+            for warning in diagnostics.warnings() {
+                tracing::warn!("{}", warning);
+            }
+            Ok((config, diagnostics))
+        });
+        metrics().record_reload(result.is_ok());
+
+        let mut current = self.current.write().unwrap();
+        match result {
+            Ok((config, diagnostics)) => {
+                let config = Arc::new(config);
+                *current = Arc::new(ConfigSnapshot {
+                    config: config.clone(),
+                    loaded_at: SystemTime::now(),
+                    watched,
+                    sources: Arc::new(sources),
+                    diagnostics: Arc::new(diagnostics),
+                });
+                Ok(config)
+            }
+            Err(err) => {
+                // 记录失败版本的修改时间，避免对同一个坏文件反复重试
+                *current = Arc::new(ConfigSnapshot {
+                    config: current.config.clone(),
+                    loaded_at: current.loaded_at,
+                    watched,
+                    sources: current.sources.clone(),
+                    diagnostics: current.diagnostics.clone(),
+                });
+                Err(err)
+            }
+        }
+    }
+
+    /// 当配置文件或其 include 的文件的修改时间变化时重新加载
+    ///
+    /// 文件未变化时返回 `Ok(None)`。
+    pub fn reload_if_changed(&self) -> Result<Option<Arc<AppConfig>>, ReloadError> {
+        // 配置文件正在被替换
+        if file_modified(&self.path).is_none() {
+            return Ok(None);
+        }
+        let changed = self
+            .snapshot()
+            .watched
+            .iter()
+            .any(|(path, modified)| file_modified(path) != *modified);
+        if !changed {
+            return Ok(None);
+        }
+        self.reload().map(Some)
+    }
+}
+
+pub(crate) fn file_modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Write, time::Duration};
+    use tempfile::NamedTempFile;
+
+    const VALID_CONFIG: &str = r#"
+[proxies]
+test = "https://example.com/clash"
+"#;
+
+    fn write_config(file: &mut NamedTempFile, content: &str) {
+        let f = file.as_file_mut();
+        f.set_len(0).unwrap();
+        std::io::Seek::rewind(f).unwrap();
+        f.write_all(content.as_bytes()).unwrap();
+        // 确保修改时间发生变化
+        let bumped = SystemTime::now() + Duration::from_secs(1);
+        f.set_modified(bumped).unwrap();
+    }
+
+    #[test]
+    fn test_reload_swaps_valid_config() {
+        let mut file = NamedTempFile::new().unwrap();
+        write_config(&mut file, VALID_CONFIG);
+        let store = ConfigStore::load(file.path()).unwrap();
+        assert_eq!(store.current().proxies.len(), 1);
+
+        write_config(
+            &mut file,
+            r#"
+[proxies]
+a = "https://a.example.com/clash"
+b = "https://b.example.com/clash"
+"#,
+        );
+        let reloaded = store.reload_if_changed().unwrap();
+        assert!(reloaded.is_some());
+        assert_eq!(store.current().proxies.len(), 2);
+
+        // 文件未变化时不重新加载
+        assert!(store.reload_if_changed().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_reload_keeps_last_good_config() {
+        let mut file = NamedTempFile::new().unwrap();
+        write_config(&mut file, VALID_CONFIG);
+        let store = ConfigStore::load(file.path()).unwrap();
+
+        // 校验失败
+        write_config(
+            &mut file,
+            r#"
+[proxies]
+test = "not-a-url"
+"#,
+        );
+        match store.reload() {
+            Err(ReloadError::Invalid(err)) => {
+                assert_eq!(err.kind(), "invalid_subscription_url");
+                // 问题带有所在的行列号
+                let diagnostic = err.diagnostics().unwrap().iter().next().unwrap();
+                let location = diagnostic.location.as_ref().unwrap();
+                assert_eq!((location.line, location.column), (3, 8));
+            }
+            other => panic!("Expected validation error, got {other:?}"),
+        }
+        assert_eq!(
+            store.current().proxies["test"].url(),
+            "https://example.com/clash"
+        );
+
+        // 解析失败
+        write_config(&mut file, "invalid toml [[[");
+        assert!(matches!(store.reload(), Err(ReloadError::Load(_))));
+        assert_eq!(store.current().proxies.len(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_caches_located_diagnostics() {
+        let mut file = NamedTempFile::new().unwrap();
+        write_config(
+            &mut file,
+            r#"
+[proxies]
+test = "not-a-url"
+"#,
+        );
+        // 首次加载不要求通过校验，问题在加载时就已定位
+        let store = ConfigStore::load(file.path()).unwrap();
+        let snapshot = store.snapshot();
+        let diagnostic = snapshot.check().errors().next().unwrap();
+        assert_eq!(diagnostic.path, "proxies.test");
+        let location = diagnostic.location.as_ref().unwrap();
+        assert_eq!((location.line, location.column), (3, 8));
+        assert!(std::ptr::eq(snapshot.check(), snapshot.check()));
+
+        write_config(&mut file, VALID_CONFIG);
+        store.reload().unwrap();
+        assert!(store.snapshot().check().is_empty());
+        assert!(store.check().is_empty());
+    }
+
+    #[test]
+    fn test_reload_when_included_file_changes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut included = NamedTempFile::new_in(dir.path()).unwrap();
+        write_config(&mut included, VALID_CONFIG);
+        let root = dir.path().join("config.toml");
+        std::fs::write(
+            &root,
+            format!(
+                "include = [{:?}]\n",
+                included.path().file_name().unwrap().to_str().unwrap()
+            ),
+        )
+        .unwrap();
+
+        let store = ConfigStore::load(&root).unwrap();
+        assert_eq!(store.current().proxies.len(), 1);
+        assert!(store.reload_if_changed().unwrap().is_none());
+
+        write_config(
+            &mut included,
+            r#"
+[proxies]
+a = "https://a.example.com/clash"
+b = "https://b.example.com/clash"
+"#,
+        );
+        assert!(store.reload_if_changed().unwrap().is_some());
+        assert_eq!(store.current().proxies.len(), 2);
+    }
+}
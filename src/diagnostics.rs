@@ -0,0 +1,205 @@
+use std::{fmt, path::PathBuf};
+
+use crate::ConfigError;
+
+/// 警告的类型名称
+const WARNING_KIND: &str = "config_warning";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// 问题在配置文件中的位置，行号和列号从 1 开始
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.column)
+    }
+}
+
+/// 配置检查发现的单个问题
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// 类型名称，错误与 [`ConfigError::kind`] 相同
+    pub kind: &'static str,
+    /// 出错的键路径，如 `rules[12].target`
+    pub path: String,
+    pub message: String,
+    /// 能定位到配置文件时的位置
+    pub location: Option<SourceLocation>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}: {}",
+            self.severity.as_str(),
+            self.path,
+            self.message
+        )?;
+        if let Some(location) = &self.location {
+            write!(f, "\n  --> {location}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 一次配置检查的全部错误和警告，按发现顺序排列
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn error(&mut self, path: impl Into<String>, error: ConfigError) {
+        self.0.push(Diagnostic {
+            severity: Severity::Error,
+            kind: error.kind(),
+            path: path.into(),
+            message: error.to_string(),
+            location: None,
+        });
+    }
+
+    pub fn warning(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.0.push(Diagnostic {
+            severity: Severity::Warning,
+            kind: WARNING_KIND,
+            path: path.into(),
+            message: message.into(),
+            location: None,
+        });
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.0.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Diagnostic> {
+        self.0.iter_mut()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Warning)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    /// 有错误时转换为 [`ConfigError::Invalid`]，只有警告时原样返回
+    pub fn into_result(self) -> Result<Self, ConfigError> {
+        if self.has_errors() {
+            Err(ConfigError::Invalid(self))
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// 每个问题占一段，最后是统计，用于命令行输出
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diagnostic in &self.0 {
+            writeln!(f, "{diagnostic}")?;
+        }
+        let errors = self.errors().count();
+        let warnings = self.warnings().count();
+        write!(
+            f,
+            "{errors} error{}, {warnings} warning{}",
+            if errors == 1 { "" } else { "s" },
+            if warnings == 1 { "" } else { "s" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostics_display() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.error(
+            "rules[1].target",
+            ConfigError::RuleProcessingFailed("Rule target cannot be empty".to_string()),
+        );
+        diagnostics.warning("groups[2].name", "Proxy group 'A' is already defined");
+        diagnostics.iter_mut().next().unwrap().location = Some(SourceLocation {
+            file: PathBuf::from("config.toml"),
+            line: 12,
+            column: 10,
+        });
+
+        assert!(diagnostics.has_errors());
+        assert_eq!(
+            diagnostics.iter().next().unwrap().kind,
+            "rule_processing_failed"
+        );
+        assert_eq!(
+            diagnostics.to_string(),
+            "error: rules[1].target: Rule processing failed: Rule target cannot be empty\n  --> config.toml:12:10\nwarning: groups[2].name: Proxy group 'A' is already defined\n1 error, 1 warning"
+        );
+
+        // 只有警告时不算失败
+        let mut warnings = Diagnostics::default();
+        warnings.warning("auth", "no token");
+        assert!(warnings.into_result().is_ok());
+        assert!(matches!(
+            diagnostics.into_result(),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+}
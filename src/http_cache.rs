@@ -0,0 +1,69 @@
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+
+/// 计算内容的 SHA-256 十六进制摘要
+pub fn content_hash(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        // 写入长度前缀，避免不同的分段方式得到相同的摘要
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// 根据内容计算强 ETag（带引号）
+pub fn compute_etag(parts: &[&[u8]]) -> String {
+    format!("\"{}\"", content_hash(parts))
+}
+
+/// 判断 `If-None-Match` 请求头是否命中当前 ETag
+///
+/// 按照 RFC 9110 使用弱比较，支持 `*` 和逗号分隔的多个 ETag。
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+/// 格式化为 HTTP 日期（用于 `Last-Modified` 等响应头）
+pub fn http_date(time: SystemTime) -> String {
+    httpdate::fmt_http_date(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_compute_etag_is_stable() {
+        let etag = compute_etag(&[b"mode: rule\n"]);
+        assert_eq!(etag, compute_etag(&[b"mode: rule\n"]));
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        assert_eq!(etag.len(), 66);
+
+        assert_ne!(etag, compute_etag(&[b"mode: global\n"]));
+        assert_ne!(compute_etag(&[b"ab", b"c"]), compute_etag(&[b"a", b"bc"]));
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = "\"abc\"";
+        assert!(etag_matches("\"abc\"", etag));
+        assert!(etag_matches("W/\"abc\"", etag));
+        assert!(etag_matches("\"xyz\", \"abc\"", etag));
+        assert!(etag_matches("*", etag));
+        assert!(!etag_matches("\"xyz\"", etag));
+        assert!(!etag_matches("", etag));
+    }
+
+    #[test]
+    fn test_http_date() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+}
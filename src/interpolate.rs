@@ -0,0 +1,262 @@
+use std::{borrow::Cow, fs, path::Path};
+
+use toml::de::{DeTable, DeValue};
+
+use crate::app_config::Error;
+
+/// 替换配置中字符串值里的 `${VAR}`、`${VAR:-default}` 和 `${file:/path}` 引用
+///
+/// 相对的文件路径基于 `base_dir`，`$${` 表示字面量 `${`。
+pub(crate) fn interpolate_table(
+    table: &mut DeTable<'_>,
+    base_dir: &Path,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), Error> {
+    Interpolator { base_dir, env }.table(table, "")
+}
+
+/// 与 [`interpolate_table`] 相同，用于 YAML 和 JSON 配置
+pub(crate) fn interpolate_json(
+    value: &mut serde_json::Value,
+    base_dir: &Path,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), Error> {
+    Interpolator { base_dir, env }.json(value, "")
+}
+
+struct Interpolator<'a> {
+    base_dir: &'a Path,
+    env: &'a dyn Fn(&str) -> Option<String>,
+}
+
+impl Interpolator<'_> {
+    fn table(&self, table: &mut DeTable<'_>, parent: &str) -> Result<(), Error> {
+        for (key, value) in table.iter_mut() {
+            self.value(value.get_mut(), &key_path(parent, key.get_ref()))?;
+        }
+        Ok(())
+    }
+
+    fn value(&self, value: &mut DeValue<'_>, key: &str) -> Result<(), Error> {
+        match value {
+            DeValue::String(text) => {
+                if let Some(expanded) = self.expand(text, key)? {
+                    *text = Cow::Owned(expanded);
+                }
+            }
+            DeValue::Array(array) => {
+                for (index, item) in array.iter_mut().enumerate() {
+                    self.value(item.get_mut(), &format!("{key}[{index}]"))?;
+                }
+            }
+            DeValue::Table(table) => self.table(table, key)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn json(&self, value: &mut serde_json::Value, key: &str) -> Result<(), Error> {
+        match value {
+            serde_json::Value::String(text) => {
+                if let Some(expanded) = self.expand(text, key)? {
+                    *text = expanded;
+                }
+            }
+            serde_json::Value::Array(array) => {
+                for (index, item) in array.iter_mut().enumerate() {
+                    self.json(item, &format!("{key}[{index}]"))?;
+                }
+            }
+            serde_json::Value::Object(object) => {
+                for (name, item) in object.iter_mut() {
+                    self.json(item, &key_path(key, name))?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// 展开字符串中的引用，没有引用时返回 `None`
+    fn expand(&self, text: &str, key: &str) -> Result<Option<String>, Error> {
+        if !text.contains("${") {
+            return Ok(None);
+        }
+
+        let mut output = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find("${") {
+            if rest[..start].ends_with('$') {
+                output.push_str(&rest[..start - 1]);
+                output.push_str("${");
+                rest = &rest[start + 2..];
+                continue;
+            }
+            output.push_str(&rest[..start]);
+
+            let Some(len) = rest[start + 2..].find('}') else {
+                return Err(Error::InvalidInterpolation {
+                    key: key.to_string(),
+                    expression: rest[start..].to_string(),
+                });
+            };
+            let end = start + 2 + len;
+            output.push_str(&self.resolve(&rest[start + 2..end], key)?);
+            rest = &rest[end + 1..];
+        }
+        output.push_str(rest);
+        Ok(Some(output))
+    }
+
+    fn resolve(&self, expression: &str, key: &str) -> Result<String, Error> {
+        if let Some(path) = expression.strip_prefix("file:") {
+            let path = self.base_dir.join(path);
+            return match fs::read_to_string(&path) {
+                // 密钥文件通常以换行结尾
+                Ok(content) => Ok(content.trim_end_matches(['\r', '\n']).to_string()),
+                Err(error) => Err(Error::SecretFile {
+                    key: key.to_string(),
+                    path,
+                    error,
+                }),
+            };
+        }
+
+        let (name, default) = match expression.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expression, None),
+        };
+        if !is_variable_name(name) {
+            return Err(Error::InvalidInterpolation {
+                key: key.to_string(),
+                expression: format!("${{{expression}}}"),
+            });
+        }
+
+        // 与 shell 一致，变量为空时也使用默认值
+        match ((self.env)(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => Ok(default.to_string()),
+            (Some(value), _) => Ok(value),
+            (None, Some(default)) => Ok(default.to_string()),
+            (None, None) => Err(Error::MissingVariable {
+                key: key.to_string(),
+                variable: name.to_string(),
+            }),
+        }
+    }
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// 拼接点分隔的键路径，非裸键加引号，如 `proxies."my.sub"`
+pub(crate) fn key_path(parent: &str, key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    let key = if bare {
+        key.to_string()
+    } else {
+        format!("{key:?}")
+    };
+    if parent.is_empty() {
+        key
+    } else {
+        format!("{parent}.{key}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    fn interpolate(content: &str, base_dir: &Path) -> Result<toml::Table, Error> {
+        let env: HashMap<&str, &str> = HashMap::from([("TOKEN", "secret"), ("EMPTY", "")]);
+        let lookup = |name: &str| env.get(name).map(|value| value.to_string());
+
+        let mut root = DeTable::parse(content).unwrap();
+        interpolate_table(root.get_mut(), base_dir, &lookup)?;
+        Ok(serde::Deserialize::deserialize(toml::de::Deserializer::from(root)).unwrap())
+    }
+
+    #[test]
+    fn test_interpolate_env() {
+        let content = r#"
+token = "${TOKEN}"
+url = "https://example.com/sub?token=${TOKEN}&flag=${FLAG:-1}"
+empty = "${EMPTY}"
+fallback = "${EMPTY:-default}"
+escaped = "$${TOKEN}"
+plain = "$1 {x}"
+port = 7890
+list = ["${TOKEN}", "b"]
+"#;
+        let table = interpolate(content, Path::new(".")).unwrap();
+        assert_eq!(table["token"].as_str(), Some("secret"));
+        assert_eq!(
+            table["url"].as_str(),
+            Some("https://example.com/sub?token=secret&flag=1")
+        );
+        assert_eq!(table["empty"].as_str(), Some(""));
+        assert_eq!(table["fallback"].as_str(), Some("default"));
+        assert_eq!(table["escaped"].as_str(), Some("${TOKEN}"));
+        assert_eq!(table["plain"].as_str(), Some("$1 {x}"));
+        assert_eq!(table["port"].as_integer(), Some(7890));
+        assert_eq!(table["list"][0].as_str(), Some("secret"));
+    }
+
+    #[test]
+    fn test_interpolate_file() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("token"), "from-file\n").unwrap();
+        let absolute = dir.path().join("token");
+
+        let content = format!(
+            "relative = \"${{file:token}}\"\nabsolute = \"${{file:{}}}\"\n",
+            absolute.display()
+        );
+        let table = interpolate(&content, dir.path()).unwrap();
+        assert_eq!(table["relative"].as_str(), Some("from-file"));
+        assert_eq!(table["absolute"].as_str(), Some("from-file"));
+
+        let err = interpolate("[auth]\ntoken = \"${file:missing}\"", dir.path()).unwrap_err();
+        assert!(matches!(err, Error::SecretFile { ref key, .. } if key == "auth.token"));
+        assert!(err.to_string().contains("missing"));
+    }
+
+    #[test]
+    fn test_interpolate_errors() {
+        let content = r#"
+[proxies]
+"my.sub" = "https://example.com/${MISSING}"
+"#;
+        let err = interpolate(content, Path::new(".")).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::MissingVariable { ref key, ref variable }
+                if key == r#"proxies."my.sub""# && variable == "MISSING"
+        ));
+        assert_eq!(
+            err.to_string(),
+            r#"environment variable `MISSING` referenced by `proxies."my.sub"` is not set"#
+        );
+
+        let err = interpolate("[[auth.tokens]]\ntoken = \"${TOKEN\"", Path::new(".")).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InvalidInterpolation { ref key, ref expression }
+                if key == "auth.tokens[0].token" && expression == "${TOKEN"
+        ));
+
+        let err = interpolate("a = \"${1X}\"", Path::new(".")).unwrap_err();
+        assert!(matches!(err, Error::InvalidInterpolation { .. }));
+    }
+}
@@ -1,18 +1,51 @@
 mod app_config;
+mod audit_log;
+mod auth;
+mod config_loader;
+mod config_store;
+mod diagnostics;
+mod http_cache;
+mod interpolate;
+mod listener;
+mod metrics;
 mod models;
+mod overrides;
 mod proxy_group_generator;
+mod rate_limit;
+mod redact;
+mod relay;
+mod rule_mirror;
+mod tls;
+mod upstream;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use interpolate::key_path;
 
 pub use app_config::*;
+pub use audit_log::*;
+pub use auth::*;
+pub use config_store::*;
+pub use diagnostics::*;
+pub use http_cache::*;
+pub use listener::*;
+pub use metrics::*;
 pub use models::*;
+pub use overrides::*;
 pub use proxy_group_generator::*;
+pub use rate_limit::*;
+pub use redact::*;
+pub use relay::*;
+pub use rule_mirror::*;
+pub use tls::*;
+pub use upstream::*;
 
 // 默认配置常量
 const DEFAULT_HEALTH_CHECK_URL: &str = "http://www.gstatic.com/generate_204";
 const DEFAULT_HEALTH_CHECK_INTERVAL: u64 = 300;
 const DEFAULT_UPDATE_INTERVAL: u64 = 3600;
 const DEFAULT_RULE_UPDATE_INTERVAL: u64 = 86400;
+const MIN_SIGNING_KEY_LEN: usize = 16;
 
 #[derive(Debug)]
 pub enum ConfigError {
@@ -20,6 +53,8 @@ pub enum ConfigError {
     ProxyGroupGenerationFailed(String),
     RuleProcessingFailed(String),
     ConfigValidationFailed(String),
+    /// 配置检查发现的所有问题，至少包含一个错误
+    Invalid(Diagnostics),
 }
 
 impl std::fmt::Display for ConfigError {
@@ -35,52 +70,132 @@ impl std::fmt::Display for ConfigError {
             ConfigError::ConfigValidationFailed(msg) => {
                 write!(f, "Config validation failed: {msg}")
             }
+            ConfigError::Invalid(diagnostics) => {
+                for (index, diagnostic) in diagnostics.errors().enumerate() {
+                    if index > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}: {}", diagnostic.path, diagnostic.message)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
 impl std::error::Error for ConfigError {}
 
+impl ConfigError {
+    /// 错误类型名称，用于错误响应和监控指标
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ConfigError::InvalidSubscriptionUrl(_) => "invalid_subscription_url",
+            ConfigError::ProxyGroupGenerationFailed(_) => "proxy_group_generation_failed",
+            ConfigError::RuleProcessingFailed(_) => "rule_processing_failed",
+            ConfigError::ConfigValidationFailed(_) => "config_validation_failed",
+            // 以第一个错误为准，各个错误的类型见其中的条目
+            ConfigError::Invalid(diagnostics) => diagnostics
+                .errors()
+                .next()
+                .map_or("config_validation_failed", |diagnostic| diagnostic.kind),
+        }
+    }
+
+    /// 配置检查发现的问题，其他错误返回 `None`
+    pub fn diagnostics(&self) -> Option<&Diagnostics> {
+        match self {
+            ConfigError::Invalid(diagnostics) => Some(diagnostics),
+            _ => None,
+        }
+    }
+
+    /// 在错误信息前标注出错的配置档
+    pub fn in_profile(self, profile: &str) -> Self {
+        self.with_context(&format!("profile '{profile}'"))
+    }
+
+    /// 在错误信息前标注出错的 token 条目
+    pub fn in_token(self, label: &str) -> Self {
+        self.with_context(&format!("token '{label}'"))
+    }
+
+    fn with_context(self, context: &str) -> Self {
+        let prefix = |msg: String| format!("{context}: {msg}");
+        match self {
+            ConfigError::InvalidSubscriptionUrl(msg) => {
+                ConfigError::InvalidSubscriptionUrl(prefix(msg))
+            }
+            ConfigError::ProxyGroupGenerationFailed(msg) => {
+                ConfigError::ProxyGroupGenerationFailed(prefix(msg))
+            }
+            ConfigError::RuleProcessingFailed(msg) => {
+                ConfigError::RuleProcessingFailed(prefix(msg))
+            }
+            ConfigError::ConfigValidationFailed(msg) => {
+                ConfigError::ConfigValidationFailed(prefix(msg))
+            }
+            ConfigError::Invalid(mut diagnostics) => {
+                for diagnostic in diagnostics.iter_mut() {
+                    diagnostic.message = prefix(std::mem::take(&mut diagnostic.message));
+                }
+                ConfigError::Invalid(diagnostics)
+            }
+        }
+    }
+}
+
 /// 生成 proxy providers
+///
+/// 订阅源单独的设置优先，其次是 `provider-config` 中的值，最后是默认值。
 fn generate_proxy_providers(
-    proxies: &HashMap<String, String>,
+    proxies: &HashMap<String, ProxySource>,
     provider_config: &Option<ProviderConfig>,
-) -> HashMap<String, ProxyProvider> {
-    let mut providers = HashMap::new();
+) -> BTreeMap<String, ProxyProvider> {
+    let mut providers = BTreeMap::new();
 
-    for (name, url) in proxies {
+    for (name, source) in proxies {
+        let source_config = source.config();
+        let check = source_config.and_then(|c| c.health_check.as_ref());
         let health_check = HealthCheck {
-            enable: true,
-            url: provider_config
-                .as_ref()
-                .and_then(|c| c.health_check_url.clone())
+            enable: check.and_then(|c| c.enable).unwrap_or(true),
+            url: check
+                .and_then(|c| c.url.clone())
+                .or_else(|| {
+                    provider_config
+                        .as_ref()
+                        .and_then(|c| c.health_check_url.clone())
+                })
                 .unwrap_or_else(|| DEFAULT_HEALTH_CHECK_URL.to_string()),
-            interval: provider_config
-                .as_ref()
-                .and_then(|c| c.health_check_interval)
+            interval: check
+                .and_then(|c| c.interval)
+                .or(provider_config
+                    .as_ref()
+                    .and_then(|c| c.health_check_interval))
                 .unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL),
-            lazy: provider_config.as_ref().and_then(|c| c.lazy),
+            lazy: check
+                .and_then(|c| c.lazy)
+                .or(provider_config.as_ref().and_then(|c| c.lazy)),
         };
 
         providers.insert(
             name.clone(),
             ProxyProvider::Http(HttpProxyProvider {
-                url: url.clone(),
+                url: source.url().to_string(),
                 path: Some(format!("./proxies/{name}.yaml")),
                 common: ProxyProviderCommon {
-                    interval: provider_config
-                        .as_ref()
-                        .and_then(|c| c.update_interval)
+                    interval: source_config
+                        .and_then(|c| c.interval)
+                        .or(provider_config.as_ref().and_then(|c| c.update_interval))
                         .or(Some(DEFAULT_UPDATE_INTERVAL)),
                     health_check: Some(health_check),
-                    filter: None,
-                    exclude_filter: None,
-                    exclude_type: None,
-                    r#override: None,
+                    filter: source_config.and_then(|c| c.filter.clone()),
+                    exclude_filter: source_config.and_then(|c| c.exclude_filter.clone()),
+                    exclude_type: source_config.and_then(|c| c.exclude_type.clone()),
+                    r#override: source_config.and_then(|c| c.r#override.clone()),
                 },
-                proxy: None,
-                size_limit: None,
-                header: None,
+                proxy: source_config.and_then(|c| c.proxy.clone()),
+                size_limit: source_config.and_then(|c| c.size_limit),
+                header: source_config.and_then(|c| c.header.clone()),
             }),
         );
     }
@@ -101,8 +216,8 @@ pub fn generate_clash_config(app_config: AppConfig) -> Config {
     // 生成地区代理组（如果启用）
     let region_groups = if let Some(region_config) = &app_config.region_groups {
         if region_config.enabled {
-            let mut provider_names: Vec<String> = proxy_providers.keys().cloned().collect();
-            provider_names.sort(); // 确保提供者顺序一致
+            // BTreeMap 的键已按顺序排列，确保提供者顺序一致
+            let provider_names: Vec<String> = proxy_providers.keys().cloned().collect();
             ProxyGroupTemplateGenerator::generate_region_groups(&provider_names, region_config)
         } else {
             Vec::new()
@@ -153,8 +268,8 @@ fn apply_default_config(config: &mut Config, default_config: &Option<DefaultConf
 /// 生成规则和规则提供者
 fn generate_rules_and_providers(
     rules_config: &[RuleCfg],
-) -> (HashMap<String, RuleProvider>, Vec<Rule>) {
-    let mut rule_providers = HashMap::new();
+) -> (BTreeMap<String, RuleProvider>, Vec<Rule>) {
+    let mut rule_providers = BTreeMap::new();
     let mut rules = Vec::new();
 
     for rule_cfg in rules_config {
@@ -186,78 +301,362 @@ fn generate_rules_and_providers(
     (rule_providers, rules)
 }
 
-/// 验证应用配置
+/// 验证应用配置，有错误时返回包含所有问题的 [`ConfigError::Invalid`]
 pub fn validate_app_config(app_config: &AppConfig) -> Result<(), ConfigError> {
-    // 验证订阅 URL
-    for (name, url) in &app_config.proxies {
+    check_app_config(app_config).into_result().map(drop)
+}
+
+/// 检查应用配置，收集所有错误和警告
+///
+/// 每个问题都带有出错的键路径，如 `rules[12].target`。
+pub fn check_app_config(app_config: &AppConfig) -> Diagnostics {
+    let mut diagnostics = Diagnostics::default();
+
+    // 验证订阅源，按名称排序保证输出顺序稳定
+    let mut proxies: Vec<_> = app_config.proxies.iter().collect();
+    proxies.sort_by_key(|(name, _)| *name);
+    for (name, source) in proxies {
+        let path = key_path("proxies", name);
+        let url = source.url();
         if !is_valid_url(url) {
-            return Err(ConfigError::InvalidSubscriptionUrl(format!(
-                "{name}: {url}"
-            )));
+            let url_path = match source {
+                ProxySource::Url(_) => path.clone(),
+                ProxySource::Provider(_) => format!("{path}.url"),
+            };
+            diagnostics.error(
+                url_path,
+                ConfigError::InvalidSubscriptionUrl(format!("{name}: {url}")),
+            );
+        }
+        if let Some(check_url) = source
+            .config()
+            .and_then(|c| c.health_check.as_ref())
+            .and_then(|c| c.url.as_ref())
+            && !is_valid_url(check_url)
+        {
+            diagnostics.error(
+                format!("{path}.health-check.url"),
+                ConfigError::ConfigValidationFailed(format!(
+                    "Invalid health check URL for provider {name}: {check_url}"
+                )),
+            );
         }
     }
 
     // 验证地区代理组配置
-    if let Some(region_config) = &app_config.region_groups {
-        if region_config.enabled {
-            for region in &region_config.regions {
-                if let Err(e) = ProxyGroupTemplateGenerator::validate_filter(&region.filter) {
-                    return Err(ConfigError::ProxyGroupGenerationFailed(format!(
+    if let Some(region_config) = &app_config.region_groups
+        && region_config.enabled
+    {
+        for (index, region) in region_config.regions.iter().enumerate() {
+            if let Err(e) = ProxyGroupTemplateGenerator::validate_filter(&region.filter) {
+                diagnostics.error(
+                    format!("region-groups.regions[{index}].filter"),
+                    ConfigError::ProxyGroupGenerationFailed(format!(
                         "Invalid filter for region {}: {}",
                         region.name, e
-                    )));
-                }
+                    )),
+                );
             }
         }
     }
 
+    // 同名的代理组在客户端中只有一个生效
+    let mut group_names = HashMap::new();
+    for (index, group) in app_config.groups.iter().enumerate() {
+        let name = group_name(group);
+        if let Some(first) = group_names.get(name) {
+            diagnostics.warning(
+                format!("groups[{index}].name"),
+                format!("Proxy group '{name}' is already defined at groups[{first}]"),
+            );
+        } else {
+            group_names.insert(name, index);
+        }
+    }
+
     // 获取所有可能的代理组名称（包括地区代理组）
     let available_groups = get_all_available_groups(app_config);
 
     // 验证规则配置
-    for rule_cfg in &app_config.rules {
+    let mut match_rule = None;
+    let mut rule_sets = HashMap::new();
+    for (index, rule_cfg) in app_config.rules.iter().enumerate() {
+        let path = format!("rules[{index}]");
+        if let Some(match_index) = match_rule {
+            diagnostics.warning(
+                &path,
+                format!("Rule is never reached because rules[{match_index}] is a MATCH rule"),
+            );
+        }
         match rule_cfg {
             RuleCfg::Single(rule) => {
-                if rule.target.is_empty() {
-                    return Err(ConfigError::RuleProcessingFailed(
-                        "Rule target cannot be empty".to_string(),
-                    ));
+                if rule.tag == RuleTag::Match && match_rule.is_none() {
+                    match_rule = Some(index);
                 }
-                validate_rule_target(&rule.target, &available_groups)?;
+                check_rule_target(&mut diagnostics, &path, &rule.target, &available_groups);
             }
             RuleCfg::Set(rule_set) => {
                 if !is_valid_url(&rule_set.url) {
-                    return Err(ConfigError::RuleProcessingFailed(format!(
-                        "Invalid rule set URL: {}",
-                        rule_set.url
-                    )));
+                    diagnostics.error(
+                        format!("{path}.url"),
+                        ConfigError::RuleProcessingFailed(format!(
+                            "Invalid rule set URL: {}",
+                            rule_set.url
+                        )),
+                    );
                 }
-                if rule_set.target.is_empty() {
-                    return Err(ConfigError::RuleProcessingFailed(
-                        "Rule set target cannot be empty".to_string(),
-                    ));
+                if let Some(first) = rule_sets.get(rule_set.name.as_str()) {
+                    diagnostics.warning(
+                        format!("{path}.name"),
+                        format!(
+                            "Rule set '{}' is already defined at rules[{first}], this definition replaces it",
+                            rule_set.name
+                        ),
+                    );
+                } else {
+                    rule_sets.insert(rule_set.name.as_str(), index);
                 }
-                validate_rule_target(&rule_set.target, &available_groups)?;
+                check_rule_target(&mut diagnostics, &path, &rule_set.target, &available_groups);
             }
         }
     }
 
+    // 验证服务配置
+    if let Some(server) = &app_config.server
+        && let Some(public_url) = &server.public_url
+        && !is_valid_url(public_url)
+    {
+        diagnostics.error(
+            "server.public-url",
+            ConfigError::ConfigValidationFailed(format!("Invalid public URL: {public_url}")),
+        );
+    }
+
+    // 验证配置档
+    let mut profiles: Vec<_> = app_config.profiles.iter().collect();
+    profiles.sort_by_key(|(name, _)| *name);
+    for (name, profile) in profiles {
+        let profile_path = key_path("profiles", name);
+        if name.is_empty() || name.contains('/') {
+            diagnostics.error(
+                &profile_path,
+                ConfigError::ConfigValidationFailed(format!("Invalid profile name: '{name}'")),
+            );
+            continue;
+        }
+        let Some(profile_config) = app_config.with_profile(name) else {
+            continue;
+        };
+        for mut diagnostic in check_app_config(&profile_config) {
+            // 配置档替换的字段定位到配置档中，其余字段的问题与基础配置相同时不重复报告
+            let overridden = match diagnostic.path.split(['.', '[']).next() {
+                Some("groups") => profile.groups.is_some(),
+                Some("rules") => profile.rules.is_some(),
+                Some("region-groups") => profile.region_groups.is_some(),
+                _ => false,
+            };
+            if overridden {
+                diagnostic.path = format!("{profile_path}.{}", diagnostic.path);
+            } else if diagnostics.iter().any(|existing| *existing == diagnostic) {
+                continue;
+            } else {
+                diagnostic.message = format!("profile '{name}': {}", diagnostic.message);
+            }
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    // 验证认证配置
+    if let Some(auth_config) = &app_config.auth {
+        check_auth_config(&mut diagnostics, app_config, auth_config);
+    }
+
+    diagnostics
+}
+
+/// 验证规则目标
+fn check_rule_target(
+    diagnostics: &mut Diagnostics,
+    rule_path: &str,
+    target: &str,
+    available_groups: &[String],
+) {
+    let path = format!("{rule_path}.target");
+    if target.is_empty() {
+        diagnostics.error(
+            path,
+            ConfigError::RuleProcessingFailed("Rule target cannot be empty".to_string()),
+        );
+    } else if let Err(err) = validate_rule_target(target, available_groups) {
+        diagnostics.error(path, err);
+    }
+}
+
+/// 验证认证配置和所有 token 条目
+fn check_auth_config(
+    diagnostics: &mut Diagnostics,
+    app_config: &AppConfig,
+    auth_config: &AuthConfig,
+) {
+    if auth_config.enabled
+        && auth_config.token.as_deref().unwrap_or("").is_empty()
+        && auth_config.tokens.is_empty()
+        && auth_config.signing_key.is_none()
+    {
+        diagnostics.warning(
+            "auth",
+            "Authentication is enabled but no token is configured, every request will be rejected",
+        );
+    }
+    if let Some(token) = &auth_config.token
+        && let Err(msg) = check_stored_token(token)
+    {
+        diagnostics.error("auth.token", ConfigError::ConfigValidationFailed(msg));
+    }
+    if let Some(token) = &auth_config.token {
+        check_token_length(diagnostics, "auth.token", token);
+    }
+    // 每个错误的 token 都要对所有 argon2 哈希计算一次，必须限制猜测的速度
+    let uses_argon2 = auth_config
+        .token
+        .iter()
+        .chain(auth_config.tokens.iter().map(|entry| &entry.token))
+        .any(|token| auth::is_argon2_hash(token));
+    let limits_guesses = app_config
+        .server
+        .as_ref()
+        .and_then(|server| server.rate_limit.as_ref())
+        .is_some_and(|limit| {
+            limit.per_ip.is_some_and(|per_ip| per_ip > 0)
+                || limit.max_failed_attempts.is_some_and(|max| max > 0)
+        });
+    if auth_config.enabled && uses_argon2 && !limits_guesses {
+        diagnostics.error(
+            "auth",
+            ConfigError::ConfigValidationFailed(
+                "Argon2 token hashes require per-ip or max-failed-attempts in [server.rate-limit]"
+                    .to_string(),
+            ),
+        );
+    }
+    if let Some(key) = &auth_config.signing_key
+        && key.len() < MIN_SIGNING_KEY_LEN
+    {
+        diagnostics.error(
+            "auth.signing-key",
+            ConfigError::ConfigValidationFailed(format!(
+                "Signing key must be at least {MIN_SIGNING_KEY_LEN} characters"
+            )),
+        );
+    }
+
+    let mut labels = HashSet::new();
+    let mut tokens = HashSet::new();
+    let now = unix_now();
+    for (index, entry) in auth_config.tokens.iter().enumerate() {
+        let path = format!("auth.tokens[{index}]");
+        if entry.label.is_empty() {
+            diagnostics.error(
+                format!("{path}.label"),
+                ConfigError::ConfigValidationFailed("Token label cannot be empty".to_string()),
+            );
+        } else if !labels.insert(entry.label.as_str()) {
+            diagnostics.error(
+                format!("{path}.label"),
+                ConfigError::ConfigValidationFailed(format!(
+                    "Duplicate token label: '{}'",
+                    entry.label
+                )),
+            );
+        }
+        if let Err((field, err)) = validate_token_entry(app_config, entry) {
+            diagnostics.error(format!("{path}.{field}"), err.in_token(&entry.label));
+        }
+        check_token_length(diagnostics, format!("{path}.token"), &entry.token);
+        // 哈希加了盐，同一 token 的不同哈希在这里发现不了，由 `hash-token` 生成时提示
+        if !entry.token.is_empty()
+            && (!tokens.insert(entry.token.as_str())
+                || auth_config.token.as_deref() == Some(entry.token.as_str()))
+        {
+            diagnostics.error(
+                format!("{path}.token"),
+                ConfigError::ConfigValidationFailed("Token is already in use".to_string())
+                    .in_token(&entry.label),
+            );
+        }
+        if entry.enabled
+            && let Some(expires_at) = entry.expires_at
+            && expires_at <= now
+        {
+            diagnostics.warning(
+                format!("{path}.expires-at"),
+                format!("Token '{}' has expired", entry.label),
+            );
+        }
+    }
+}
+
+/// 过短的明文 token 容易猜到，日志脱敏也不会遮盖它
+fn check_token_length(diagnostics: &mut Diagnostics, path: impl Into<String>, token: &str) {
+    if !token.is_empty() && token.len() < redact::MIN_SECRET_LEN {
+        diagnostics.warning(
+            path,
+            format!(
+                "Token is shorter than {} characters and will not be masked in logs",
+                redact::MIN_SECRET_LEN
+            ),
+        );
+    }
+}
+
+/// 验证单个 token 条目绑定的配置档和覆盖项，出错时同时返回出错的字段
+fn validate_token_entry(
+    app_config: &AppConfig,
+    entry: &TokenEntry,
+) -> Result<(), (&'static str, ConfigError)> {
+    if entry.token.is_empty() {
+        return Err((
+            "token",
+            ConfigError::ConfigValidationFailed("Token cannot be empty".to_string()),
+        ));
+    }
+    check_stored_token(&entry.token)
+        .map_err(|msg| ("token", ConfigError::ConfigValidationFailed(msg)))?;
+
+    let mut config = match &entry.profile {
+        Some(profile) => app_config.with_profile(profile).ok_or_else(|| {
+            (
+                "profile",
+                ConfigError::ConfigValidationFailed(format!("Unknown profile: '{profile}'")),
+            )
+        })?,
+        None => app_config.clone(),
+    };
+    if let Some(overrides) = &entry.overrides {
+        overrides
+            .apply(&mut config)
+            .map_err(|err| ("overrides", err))?;
+    }
     Ok(())
 }
 
+fn group_name(group: &ProxyGroup) -> &str {
+    match group {
+        ProxyGroup::Select(select) => &select.common.name,
+        ProxyGroup::UrlTest(url_test) => &url_test.common.name,
+        ProxyGroup::Fallback(fallback) => &fallback.common.name,
+        ProxyGroup::LoadBalance(load_balance) => &load_balance.common.name,
+        ProxyGroup::Relay(relay) => &relay.common.name,
+    }
+}
+
 /// 获取所有可用的代理组名称
 fn get_all_available_groups(app_config: &AppConfig) -> Vec<String> {
     let mut groups = Vec::new();
 
     // 添加用户定义的代理组
     for group in &app_config.groups {
-        match group {
-            ProxyGroup::Select(select) => groups.push(select.common.name.clone()),
-            ProxyGroup::UrlTest(url_test) => groups.push(url_test.common.name.clone()),
-            ProxyGroup::Fallback(fallback) => groups.push(fallback.common.name.clone()),
-            ProxyGroup::LoadBalance(load_balance) => groups.push(load_balance.common.name.clone()),
-            ProxyGroup::Relay(relay) => groups.push(relay.common.name.clone()),
-        }
+        groups.push(group_name(group).to_string());
     }
 
     // 添加地区代理组（如果启用）
@@ -433,10 +832,11 @@ mod tests {
         let mut proxies = HashMap::new();
         proxies.insert(
             "test-provider".to_string(),
-            "https://example.com/clash".to_string(),
+            "https://example.com/clash".into(),
         );
 
         AppConfig {
+            include: Vec::new(),
             proxies,
             groups: vec![ProxyGroup::Select(SelectGroup {
                 common: ProxyGroupCommon {
@@ -493,13 +893,15 @@ mod tests {
                 lazy: Some(false),
             }),
             auth: None,
+            profiles: HashMap::new(),
+            server: None,
         }
     }
 
     #[test]
     fn test_generate_proxy_providers() {
         let mut proxies = HashMap::new();
-        proxies.insert("test".to_string(), "https://example.com/clash".to_string());
+        proxies.insert("test".to_string(), "https://example.com/clash".into());
 
         let provider_config = Some(ProviderConfig {
             health_check_url: Some("http://test.com".to_string()),
@@ -531,7 +933,7 @@ mod tests {
     #[test]
     fn test_generate_proxy_providers_with_defaults() {
         let mut proxies = HashMap::new();
-        proxies.insert("test".to_string(), "https://example.com/clash".to_string());
+        proxies.insert("test".to_string(), "https://example.com/clash".into());
 
         let providers = generate_proxy_providers(&proxies, &None);
 
@@ -547,6 +949,61 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_generate_proxy_providers_with_source_config() {
+        let mut proxies = HashMap::new();
+        proxies.insert(
+            "custom".to_string(),
+            ProxySource::Provider(Box::new(ProxySourceConfig {
+                url: "https://example.com/custom".to_string(),
+                interval: Some(600),
+                health_check: Some(HealthCheckConfig {
+                    enable: Some(false),
+                    ..Default::default()
+                }),
+                exclude_filter: Some("过期".to_string()),
+                header: Some(BTreeMap::from([(
+                    "User-Agent".to_string(),
+                    vec!["clash.meta".to_string()],
+                )])),
+                proxy: Some("DIRECT".to_string()),
+                ..Default::default()
+            })),
+        );
+        let provider_config = Some(ProviderConfig {
+            health_check_url: Some("http://test.com".to_string()),
+            health_check_interval: Some(900),
+            update_interval: Some(7200),
+            lazy: Some(true),
+        });
+
+        let providers = generate_proxy_providers(&proxies, &provider_config);
+
+        match providers.get("custom").unwrap() {
+            ProxyProvider::Http(http_provider) => {
+                assert_eq!(http_provider.url, "https://example.com/custom");
+                assert_eq!(
+                    http_provider.path,
+                    Some("./proxies/custom.yaml".to_string())
+                );
+                assert_eq!(http_provider.common.interval, Some(600));
+                assert_eq!(http_provider.common.exclude_filter.as_deref(), Some("过期"));
+                assert_eq!(http_provider.common.filter, None);
+                assert_eq!(http_provider.proxy.as_deref(), Some("DIRECT"));
+                let header = http_provider.header.as_ref().unwrap();
+                assert_eq!(header["User-Agent"], vec!["clash.meta".to_string()]);
+
+                // 未设置的健康检查字段使用 provider-config 中的值
+                let health_check = http_provider.common.health_check.as_ref().unwrap();
+                assert!(!health_check.enable);
+                assert_eq!(health_check.url, "http://test.com");
+                assert_eq!(health_check.interval, 900);
+                assert_eq!(health_check.lazy, Some(true));
+            }
+            _ => panic!("Expected HTTP provider"),
+        }
+    }
+
     #[test]
     fn test_apply_default_config() {
         let mut config = Config::default();
@@ -660,14 +1117,77 @@ mod tests {
         let mut app_config = create_test_app_config();
         app_config
             .proxies
-            .insert("invalid".to_string(), "not-a-url".to_string());
+            .insert("invalid".to_string(), "not-a-url".into());
 
         let result = validate_app_config(&app_config);
         assert!(result.is_err());
-        match result.unwrap_err() {
-            ConfigError::InvalidSubscriptionUrl(_) => {}
-            _ => panic!("Expected InvalidSubscriptionUrl error"),
-        }
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), "invalid_subscription_url");
+        let diagnostics: Vec<_> = err.diagnostics().unwrap().iter().collect();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].path, "proxies.invalid");
+
+        // 表形式的订阅源指向出错的字段
+        let mut app_config = create_test_app_config();
+        app_config.proxies.insert(
+            "table".to_string(),
+            ProxySource::Provider(Box::new(ProxySourceConfig {
+                url: "not-a-url".to_string(),
+                health_check: Some(HealthCheckConfig {
+                    url: Some("generate_204".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })),
+        );
+        let paths: Vec<_> = check_app_config(&app_config)
+            .iter()
+            .map(|diagnostic| diagnostic.path.clone())
+            .collect();
+        assert_eq!(
+            paths,
+            vec!["proxies.table.url", "proxies.table.health-check.url"]
+        );
+    }
+
+    #[test]
+    fn test_check_app_config_collects_all() {
+        let mut app_config = create_test_app_config();
+        app_config
+            .proxies
+            .insert("bad".to_string(), "not-a-url".into());
+        app_config.rules.insert(
+            0,
+            RuleCfg::Single(RuleSingleCfg {
+                tag: RuleTag::Match,
+                value: String::new(),
+                target: "Missing".to_string(),
+            }),
+        );
+        app_config.groups.push(app_config.groups[0].clone());
+
+        let diagnostics = check_app_config(&app_config);
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.path.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Severity::Error, "proxies.bad"),
+                (Severity::Warning, "groups[1].name"),
+                (Severity::Error, "rules[0].target"),
+                (Severity::Warning, "rules[1]"),
+                (Severity::Warning, "rules[2]"),
+            ]
+        );
+
+        // 只有警告时校验通过
+        let mut app_config = create_test_app_config();
+        app_config.groups.push(app_config.groups[0].clone());
+        let diagnostics = check_app_config(&app_config);
+        assert_eq!(diagnostics.warnings().count(), 1);
+        assert!(validate_app_config(&app_config).is_ok());
     }
 
     #[test]
@@ -681,10 +1201,154 @@ mod tests {
 
         let result = validate_app_config(&app_config);
         assert!(result.is_err());
-        match result.unwrap_err() {
-            ConfigError::RuleProcessingFailed(_) => {}
-            _ => panic!("Expected RuleProcessingFailed error"),
-        }
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), "rule_processing_failed");
+        assert_eq!(
+            err.diagnostics().unwrap().iter().next().unwrap().path,
+            "rules[2].target"
+        );
+    }
+
+    #[test]
+    fn test_validate_app_config_invalid_profile() {
+        let mut app_config = create_test_app_config();
+        app_config.profiles.insert(
+            "phone".to_string(),
+            ProfileConfig {
+                rules: Some(vec![RuleCfg::Single(RuleSingleCfg {
+                    tag: RuleTag::Match,
+                    value: String::new(),
+                    target: "NonExistentGroup".to_string(),
+                })]),
+                ..Default::default()
+            },
+        );
+
+        let err = validate_app_config(&app_config).unwrap_err();
+        assert_eq!(err.kind(), "rule_processing_failed");
+        let diagnostic = err.diagnostics().unwrap().iter().next().unwrap();
+        assert_eq!(diagnostic.path, "profiles.phone.rules[0].target");
+
+        // 配置档没有替换的字段出错时，在信息中标注配置档
+        app_config.profiles.insert(
+            "phone".to_string(),
+            ProfileConfig {
+                groups: Some(Vec::new()),
+                ..Default::default()
+            },
+        );
+        let err = validate_app_config(&app_config).unwrap_err();
+        let diagnostics: Vec<_> = err.diagnostics().unwrap().iter().collect();
+        assert!(
+            diagnostics
+                .iter()
+                .any(|d| d.path == "rules[1].target" && d.message.starts_with("profile 'phone': ")),
+            "{diagnostics:?}"
+        );
+    }
+
+    #[test]
+    fn test_validate_app_config_token_entries() {
+        let entry = |label: &str, token: &str| TokenEntry {
+            label: label.to_string(),
+            token: token.to_string(),
+            enabled: true,
+            expires_at: None,
+            profile: None,
+            overrides: None,
+        };
+        let with_tokens = |token: Option<&str>, tokens: Vec<TokenEntry>| {
+            let mut app_config = create_test_app_config();
+            app_config.profiles.insert("phone".to_string(), ProfileConfig::default());
+            app_config.auth = Some(AuthConfig {
+                enabled: true,
+                token: token.map(str::to_string),
+                tokens,
+                signing_key: None,
+            });
+            app_config
+        };
+
+        let valid = with_tokens(
+            Some("shared"),
+            vec![
+                TokenEntry {
+                    profile: Some("phone".to_string()),
+                    ..entry("alice", "a")
+                },
+                entry("bob", "b"),
+            ],
+        );
+        assert!(validate_app_config(&valid).is_ok());
+
+        // 过短的 token 不会被日志脱敏遮盖，给出警告
+        let warnings: Vec<_> = check_app_config(&valid)
+            .iter()
+            .filter(|d| d.severity == Severity::Warning)
+            .map(|d| d.path.clone())
+            .collect();
+        assert_eq!(
+            warnings,
+            ["auth.token", "auth.tokens[0].token", "auth.tokens[1].token"]
+        );
+        let long = with_tokens(Some("shared-secret"), vec![entry("alice", "alice-secret")]);
+        assert!(check_app_config(&long).is_empty());
+
+        // 重复的名称或 token
+        let invalid = with_tokens(None, vec![entry("alice", "a"), entry("alice", "b")]);
+        assert!(validate_app_config(&invalid).is_err());
+        let invalid = with_tokens(None, vec![entry("alice", "a"), entry("bob", "a")]);
+        assert!(validate_app_config(&invalid).is_err());
+        let invalid = with_tokens(Some("a"), vec![entry("alice", "a")]);
+        assert!(validate_app_config(&invalid).is_err());
+        let invalid = with_tokens(None, vec![entry("alice", "")]);
+        assert!(validate_app_config(&invalid).is_err());
+
+        // 绑定不存在的配置档或订阅源
+        let invalid = with_tokens(
+            None,
+            vec![TokenEntry {
+                profile: Some("tablet".to_string()),
+                ..entry("alice", "a")
+            }],
+        );
+        let err = validate_app_config(&invalid).unwrap_err();
+        assert_eq!(err.kind(), "config_validation_failed");
+        let diagnostic = err.diagnostics().unwrap().iter().next().unwrap();
+        assert_eq!(diagnostic.path, "auth.tokens[0].profile");
+        assert!(
+            diagnostic
+                .message
+                .starts_with("Config validation failed: token 'alice': ")
+        );
+        let invalid = with_tokens(
+            None,
+            vec![TokenEntry {
+                overrides: Some(SubscriptionOverrides {
+                    only: Some(vec!["missing".to_string()]),
+                    ..Default::default()
+                }),
+                ..entry("alice", "a")
+            }],
+        );
+        assert!(validate_app_config(&invalid).is_err());
+
+        // argon2 哈希要求限制猜测速度
+        let hash = hash_token("alice-secret", TokenHashScheme::Argon2);
+        let mut argon2 = with_tokens(None, vec![entry("alice", &hash)]);
+        let err = validate_app_config(&argon2).unwrap_err();
+        let diagnostic = err.diagnostics().unwrap().iter().next().unwrap();
+        assert_eq!(diagnostic.path, "auth");
+        argon2.server = Some(ServerConfig {
+            rate_limit: Some(RateLimitConfig {
+                per_ip: None,
+                per_token: None,
+                max_failed_attempts: Some(10),
+                lockout_seconds: 900,
+            }),
+            ..Default::default()
+        });
+        assert!(validate_app_config(&argon2).is_ok());
     }
 
     #[test]
@@ -0,0 +1,399 @@
+use std::{
+    io,
+    net::SocketAddr,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    extract::connect_info::Connected,
+    serve::{IncomingStream, Listener},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{TlsAcceptor, rustls::ServerConfig, server::TlsStream};
+
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// TLS 握手超时时间，避免慢速客户端长期占用连接
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 同时等待交给服务的已握手连接数量
+const TLS_ACCEPT_BACKLOG: usize = 64;
+
+/// systemd 传递的第一个文件描述符
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+/// 服务监听的地址，明文 HTTP、HTTPS 或 Unix socket
+pub enum ServerListener {
+    Tcp(TcpListener),
+    Tls(TlsListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl ServerListener {
+    /// 监听 TCP 地址，提供 TLS 配置时使用 HTTPS
+    pub async fn bind(addr: &str, tls: Option<Arc<ServerConfig>>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        Self::from_tcp(listener, tls)
+    }
+
+    fn from_tcp(listener: TcpListener, tls: Option<Arc<ServerConfig>>) -> io::Result<Self> {
+        Ok(match tls {
+            Some(config) => ServerListener::Tls(TlsListener::new(listener, config)?),
+            None => ServerListener::Tcp(listener),
+        })
+    }
+
+    /// 监听 Unix socket，`mode` 为 socket 文件的权限
+    ///
+    /// 上次运行遗留的 socket 文件会被删除，其他类型的文件不会被覆盖。
+    #[cfg(unix)]
+    pub fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<Self> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        if let Some(mode) = mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+        Ok(ServerListener::Unix(listener))
+    }
+
+    /// 使用 systemd socket 激活传入的监听 socket
+    ///
+    /// 未通过 socket 激活启动时返回 `Ok(None)`。
+    #[cfg(unix)]
+    pub fn from_systemd(tls: Option<Arc<ServerConfig>>) -> io::Result<Option<Self>> {
+        use std::os::fd::{FromRawFd, IntoRawFd};
+
+        let count = listen_fds(
+            std::env::var("LISTEN_PID").ok().as_deref(),
+            std::env::var("LISTEN_FDS").ok().as_deref(),
+            std::process::id(),
+        );
+        if count == 0 {
+            return Ok(None);
+        }
+        if count > 1 {
+            tracing::warn!("Using the first of {} sockets passed by systemd", count);
+        }
+
+        // SAFETY: systemd 将从 3 开始的文件描述符交给本进程，其他代码不会使用它
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+        if listener.local_addr().is_ok() {
+            listener.set_nonblocking(true)?;
+            return Self::from_tcp(TcpListener::from_std(listener)?, tls).map(Some);
+        }
+
+        // 不是 TCP socket，按 Unix socket 处理
+        // SAFETY: 所有权从上面的 TcpListener 转移而来
+        let listener =
+            unsafe { std::os::unix::net::UnixListener::from_raw_fd(listener.into_raw_fd()) };
+        listener.local_addr()?;
+        if tls.is_some() {
+            return Err(io::Error::other("TLS is not supported on unix sockets"));
+        }
+        listener.set_nonblocking(true)?;
+        Ok(Some(ServerListener::Unix(UnixListener::from_std(
+            listener,
+        )?)))
+    }
+}
+
+/// 根据 `LISTEN_PID` 和 `LISTEN_FDS` 计算 systemd 传入的 socket 数量
+#[cfg(unix)]
+fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> usize {
+    if listen_pid.and_then(|value| value.parse::<u32>().ok()) != Some(pid) {
+        return 0;
+    }
+    listen_fds.and_then(|value| value.parse().ok()).unwrap_or(0)
+}
+
+impl Listener for ServerListener {
+    type Io = ServerStream;
+    /// Unix socket 连接没有 IP 地址
+    type Addr = Option<SocketAddr>;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self {
+            ServerListener::Tcp(listener) => {
+                let (stream, addr) = Listener::accept(listener).await;
+                (ServerStream::Tcp(stream), Some(addr))
+            }
+            ServerListener::Tls(listener) => {
+                let (stream, addr) = listener.accept().await;
+                (ServerStream::Tls(Box::new(stream)), Some(addr))
+            }
+            #[cfg(unix)]
+            ServerListener::Unix(listener) => {
+                let (stream, _) = Listener::accept(listener).await;
+                (ServerStream::Unix(stream), None)
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        match self {
+            ServerListener::Tcp(listener) => listener.local_addr().map(Some),
+            ServerListener::Tls(listener) => Ok(Some(listener.local_addr)),
+            #[cfg(unix)]
+            ServerListener::Unix(_) => Ok(None),
+        }
+    }
+}
+
+/// 连接的客户端地址，通过 `ConnectInfo<RemoteAddr>` 获取
+///
+/// 通过 Unix socket 连接时为 `None`。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteAddr(pub Option<SocketAddr>);
+
+impl Connected<IncomingStream<'_, ServerListener>> for RemoteAddr {
+    fn connect_info(stream: IncomingStream<'_, ServerListener>) -> Self {
+        RemoteAddr(*stream.remote_addr())
+    }
+}
+
+/// 在后台完成 TLS 握手的监听器，单个慢速握手不会阻塞其他连接
+pub struct TlsListener {
+    incoming: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, incoming) = mpsc::channel(TLS_ACCEPT_BACKLOG);
+        tokio::spawn(accept_tls(listener, TlsAcceptor::from(config), tx));
+        Ok(Self {
+            incoming,
+            local_addr,
+        })
+    }
+
+    async fn accept(&mut self) -> (TlsStream<TcpStream>, SocketAddr) {
+        match self.incoming.recv().await {
+            Some(connection) => connection,
+            // 后台任务只会随监听器一起结束
+            None => std::future::pending().await,
+        }
+    }
+}
+
+async fn accept_tls(
+    mut listener: TcpListener,
+    acceptor: TlsAcceptor,
+    tx: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+) {
+    loop {
+        let (stream, addr) = tokio::select! {
+            connection = Listener::accept(&mut listener) => connection,
+            _ = tx.closed() => return,
+        };
+        let acceptor = acceptor.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let _ = tx.send((stream, addr)).await;
+                }
+                Ok(Err(err)) => tracing::debug!("TLS handshake with {} failed: {}", addr, err),
+                Err(_) => tracing::debug!("TLS handshake with {} timed out", addr),
+            }
+        });
+    }
+}
+
+/// 已建立的连接
+pub enum ServerStream {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl AsyncRead for ServerStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ServerStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            ServerStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ServerStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ServerStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            ServerStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ServerStream::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            #[cfg(unix)]
+            ServerStream::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            ServerStream::Tcp(stream) => stream.is_write_vectored(),
+            ServerStream::Tls(stream) => stream.is_write_vectored(),
+            #[cfg(unix)]
+            ServerStream::Unix(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ServerStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            ServerStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ServerStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            ServerStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TlsCertificates;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{
+        TlsConnector,
+        rustls::{ClientConfig, RootCertStore, crypto::ring, pki_types::ServerName},
+    };
+
+    #[tokio::test]
+    async fn test_tls_listener_accepts_connections() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+        std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+        let certificates = Arc::new(TlsCertificates::load(&cert_path, &key_path).unwrap());
+
+        let mut listener = ServerListener::bind("127.0.0.1:0", Some(certificates.server_config()))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap().unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.cert.der().clone()).unwrap();
+        let client_config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(client_config));
+
+        let client = async {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let client_addr = stream.local_addr().unwrap();
+            let server_name = ServerName::try_from("localhost").unwrap();
+            let mut stream = connector.connect(server_name, stream).await.unwrap();
+            stream.write_all(b"ping").await.unwrap();
+            stream.flush().await.unwrap();
+            (stream, client_addr)
+        };
+        let ((mut server_stream, remote_addr), (_client_stream, client_addr)) =
+            tokio::join!(listener.accept(), client);
+
+        // 服务端看到的是客户端的地址
+        assert_eq!(remote_addr, Some(client_addr));
+        assert!(matches!(server_stream, ServerStream::Tls(_)));
+        let mut buf = [0; 4];
+        server_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_listener() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sub-util.sock");
+
+        // 遗留的 socket 文件会被替换
+        let stale = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        drop(stale);
+        let mut listener = ServerListener::bind_unix(&path, Some(0o660)).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        assert_eq!(listener.local_addr().unwrap(), None);
+
+        let (accepted, client) = tokio::join!(listener.accept(), UnixStream::connect(&path));
+        let mut client = client.unwrap();
+        let (mut server_stream, remote_addr) = accepted;
+        assert_eq!(remote_addr, None);
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        server_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bind_unix_keeps_other_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("not-a-socket");
+        std::fs::write(&path, "data").unwrap();
+
+        assert!(ServerListener::bind_unix(&path, None).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_listen_fds() {
+        assert_eq!(listen_fds(Some("42"), Some("1"), 42), 1);
+        assert_eq!(listen_fds(Some("42"), Some("2"), 42), 2);
+        // 传给其他进程的 socket
+        assert_eq!(listen_fds(Some("41"), Some("1"), 42), 0);
+        assert_eq!(listen_fds(None, Some("1"), 42), 0);
+        assert_eq!(listen_fds(Some("42"), None, 42), 0);
+        assert_eq!(listen_fds(Some("42"), Some("x"), 42), 0);
+    }
+}
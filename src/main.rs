@@ -1,12 +1,43 @@
-use std::{path::PathBuf, process::exit};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    path::PathBuf,
+    process::exit,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
-use axum::{Extension, Router, http::StatusCode, response::Response, routing::get, extract::Query};
-use clap::Parser;
+use axum::{
+    Extension, Router,
+    body::Body,
+    extract::{ConnectInfo, MatchedPath, Path, Query, Request},
+    http::{
+        HeaderMap, StatusCode,
+        header::{
+            AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH, LAST_MODIFIED,
+            RETRY_AFTER, USER_AGENT,
+        },
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use clap::{Parser, Subcommand};
 use serde::Deserialize;
 use sub_util::{
-    AppConfig, ConfigError, generate_clash_config_with_validation, get_available_region_groups,
+    AppConfig, AuditLog, AuditQueue, AuditRecord, AuthError, ClientAddr, Config, ConfigError,
+    ConfigFormat, ConfigSnapshot, ConfigStore, METRICS_CONTENT_TYPE, ProxySource, RateLimitError,
+    RateLimiter, RedactingMakeWriter, RelayTarget, RemoteAddr, RuleCfg, RuleMirror,
+    SUBSCRIPTION_USERINFO_HEADER, ServerListener, Severity, SubscriptionOverrides,
+    SubscriptionUserinfo, TlsCertificates, TokenEntry, TokenHashScheme, UpstreamCache,
+    UpstreamRequest, bearer_token, build_public_url, collect_rule_sets, compute_etag, content_hash,
+    etag_matches, generate_clash_config_with_validation, get_available_region_groups,
+    get_rule_set_update_interval, hash_token, http_date, is_auth_enabled, metrics, redactor,
+    relay_proxy_providers, relay_rule_providers, sign_link, token_owner, unix_now,
+    validate_signed_link, validate_token,
 };
-use tracing::error;
+use tokio_rustls::rustls::ServerConfig;
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Parser, Debug)]
@@ -15,60 +46,850 @@ struct Args {
     #[arg(short, long, default_value = "config.toml")]
     config: PathBuf,
 
+    /// Format of the config file: toml, yaml or json; detected from the
+    /// extension by default
+    #[arg(long)]
+    config_format: Option<ConfigFormat>,
+
+    /// Address to listen on, `host:port` or `unix:/path/to.sock`; ignored
+    /// when systemd passes a socket through `LISTEN_FDS`
     #[arg(short, long, default_value = "0.0.0.0:3000")]
     bind: String,
+
+    /// Seconds to wait for in-flight requests after SIGTERM or SIGINT
+    #[arg(long, default_value_t = 30)]
+    shutdown_timeout: u64,
+
+    /// Permissions of the unix socket, in octal
+    #[arg(long, value_parser = parse_socket_mode)]
+    socket_mode: Option<u32>,
+
+    /// Seconds between checks of the config file for changes, 0 disables watching
+    #[arg(long, default_value_t = 2)]
+    watch_interval: u64,
+
+    /// PEM certificate chain to serve HTTPS with, overrides `[server.tls]`
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for `--tls-cert`
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Show upstream URLs and tokens in logs, error responses and `/config`
+    /// instead of masking them; for debugging only
+    #[arg(long)]
+    show_secrets: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the hash of a token for use in `auth.token` or `[[auth.tokens]]`
+    HashToken {
+        /// Token to hash, read from stdin when omitted
+        token: Option<String>,
+
+        /// Hash scheme: argon2 or sha256
+        #[arg(long, default_value = "argon2")]
+        scheme: TokenHashScheme,
+    },
+
+    /// Print a subscription link signed with `auth.signing-key` that expires
+    SignLink {
+        /// Days until the link expires
+        #[arg(long, default_value_t = 7)]
+        days: u64,
+
+        /// Profile the link points at
+        #[arg(long)]
+        profile: Option<String>,
+
+        /// Base URL of this service, defaults to `server.public-url`
+        #[arg(long)]
+        base_url: Option<String>,
+    },
+
+    /// Print the JSON Schema of the config file for editor completion and
+    /// validation
+    Schema,
+
+    /// Check the config file and print every error and warning, exiting
+    /// with status 1 when there are errors
+    Check,
 }
 
+/// How often mirrored rule sets are checked against their update interval.
+const RULE_MIRROR_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the TLS certificate files are checked for renewal.
+const TLS_RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Whether this process terminates TLS itself, which decides the scheme of
+/// URLs derived from the `Host` header.
+#[derive(Clone, Copy, Default)]
+struct ServesTls(bool);
+
+/// How often idle rate limit state is dropped.
+const RATE_LIMIT_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+/// How many audit records may wait for the writer before requests block.
+const AUDIT_QUEUE_CAPACITY: usize = 1024;
+
+/// How often expired upstream responses are dropped.
+const UPSTREAM_PRUNE_INTERVAL: Duration = Duration::from_secs(600);
+
 #[derive(Deserialize)]
 struct TokenQuery {
     token: Option<String>,
+    /// Signature of a signed link
+    sig: Option<String>,
+    /// Expiry of a signed link, as a unix timestamp
+    exp: Option<String>,
 }
 
+/// Credentials presented by the caller.
+#[derive(Clone, Copy)]
+enum Credentials<'a> {
+    None,
+    Token(&'a str),
+    /// A link signed with `auth.signing-key`
+    SignedLink {
+        expires: Option<&'a str>,
+        signature: &'a str,
+    },
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn hello_world(
-    Extension(app_config): Extension<AppConfig>,
+    Extension(store): Extension<Arc<ConfigStore>>,
+    Extension(upstream): Extension<Arc<UpstreamCache>>,
+    Extension(limiter): Extension<Arc<RateLimiter>>,
+    Extension(ServesTls(serves_tls)): Extension<ServesTls>,
     Query(query): Query<TokenQuery>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
 ) -> Response<String> {
-    // Check token authentication
-    if let Err(response) = validate_token(&app_config, &query) {
-        return response;
+    let credentials = request_credentials(&headers, None, &query);
+    serve_profile(
+        &store.snapshot(),
+        &upstream,
+        &limiter,
+        None,
+        credentials,
+        &params,
+        &headers,
+        serves_tls,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn profile_subs(
+    Extension(store): Extension<Arc<ConfigStore>>,
+    Extension(upstream): Extension<Arc<UpstreamCache>>,
+    Extension(limiter): Extension<Arc<RateLimiter>>,
+    Extension(ServesTls(serves_tls)): Extension<ServesTls>,
+    Path(segment): Path<String>,
+    Query(query): Query<TokenQuery>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response<String> {
+    let snapshot = store.snapshot();
+    let credentials = request_credentials(&headers, None, &query);
+
+    // `/subs/{token}` for clients that can neither set headers nor keep the
+    // query string; profile names take precedence
+    if matches!(credentials, Credentials::None)
+        && is_auth_enabled(&snapshot.config)
+        && !snapshot.config.profiles.contains_key(&segment)
+    {
+        return serve_profile(
+            &snapshot,
+            &upstream,
+            &limiter,
+            None,
+            Credentials::Token(&segment),
+            &params,
+            &headers,
+            serves_tls,
+        )
+        .await;
+    }
+
+    serve_profile(
+        &snapshot,
+        &upstream,
+        &limiter,
+        Some(&segment),
+        credentials,
+        &params,
+        &headers,
+        serves_tls,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn profile_token_subs(
+    Extension(store): Extension<Arc<ConfigStore>>,
+    Extension(upstream): Extension<Arc<UpstreamCache>>,
+    Extension(limiter): Extension<Arc<RateLimiter>>,
+    Extension(ServesTls(serves_tls)): Extension<ServesTls>,
+    Path((profile, path_token)): Path<(String, String)>,
+    Query(query): Query<TokenQuery>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response<String> {
+    let credentials = request_credentials(&headers, Some(&path_token), &query);
+    serve_profile(
+        &store.snapshot(),
+        &upstream,
+        &limiter,
+        Some(&profile),
+        credentials,
+        &params,
+        &headers,
+        serves_tls,
+    )
+    .await
+}
+
+/// Authenticates the request and serves the base config or a named profile.
+#[allow(clippy::too_many_arguments)]
+async fn serve_profile(
+    snapshot: &ConfigSnapshot,
+    upstream: &Arc<UpstreamCache>,
+    limiter: &RateLimiter,
+    profile: Option<&str>,
+    credentials: Credentials<'_>,
+    params: &HashMap<String, String>,
+    headers: &HeaderMap,
+    serves_tls: bool,
+) -> Response<String> {
+    let app_config = &snapshot.config;
+
+    // Check token authentication, links are signed for the profile's path
+    let path = profile.map_or_else(|| "/subs".to_string(), |profile| format!("/subs/{profile}"));
+    let entry = match authorize(app_config, limiter, &path, credentials).await {
+        Ok(entry) => entry,
+        Err(response) => return response,
+    };
+    if let Some(entry) = entry {
+        debug!("Serving subscription for token '{}'", entry.label);
+    }
+
+    let request = SubscriptionRequest {
+        params,
+        headers,
+        base_config: app_config,
+        loaded_at: snapshot.loaded_at,
+        snapshot,
+        credentials,
+        entry,
+        serves_tls,
+    };
+    let mut response = serve_authorized_profile(&request, profile, upstream).await;
+
+    let details = response
+        .extensions_mut()
+        .get_or_insert_default::<AuditDetails>();
+    details.token_label = entry.map(|entry| entry.label.clone());
+    details.profile = profile
+        .or(entry.and_then(|entry| entry.profile.as_deref()))
+        .map(str::to_string);
+    response
+}
+
+async fn serve_authorized_profile(
+    request: &SubscriptionRequest<'_>,
+    profile: Option<&str>,
+    upstream: &Arc<UpstreamCache>,
+) -> Response<String> {
+    match resolve_token_config(request.base_config, profile, request.entry) {
+        Ok(app_config) => serve_subscription(app_config, request, upstream).await,
+        Err(response) => *response,
+    }
+}
+
+/// Resolves the config a caller is served: the requested profile or the one
+/// the `[[auth.tokens]]` entry is bound to, then the entry's overrides.
+fn resolve_token_config(
+    app_config: &AppConfig,
+    profile: Option<&str>,
+    entry: Option<&TokenEntry>,
+) -> Result<AppConfig, Box<Response<String>>> {
+    // Tokens bound to a profile may only fetch that profile
+    let bound_profile = entry.and_then(|entry| entry.profile.as_deref());
+    if let (Some(requested), Some(bound)) = (profile, bound_profile)
+        && requested != bound
+    {
+        return Err(Box::new(create_json_error_response(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            &format!("Token is not allowed to access profile '{requested}'"),
+        )));
+    }
+
+    let mut config = match profile.or(bound_profile) {
+        None => app_config.clone(),
+        Some(profile) => app_config
+            .with_profile(profile)
+            .ok_or_else(|| Box::new(create_profile_not_found_response(profile)))?,
+    };
+    // Apply the token's overrides first so the query string cannot widen them
+    if let Some(overrides) = entry.and_then(|entry| entry.overrides.as_ref())
+        && let Err(err) = overrides.apply(&mut config)
+    {
+        metrics().record_generation_error(&err);
+        return Err(Box::new(create_error_response(&err)));
+    }
+    Ok(config)
+}
+
+/// What the audit log records about a response besides the request itself,
+/// attached to the response by the handlers.
+#[derive(Clone, Default)]
+struct AuditDetails {
+    token_label: Option<String>,
+    profile: Option<String>,
+    config_hash: Option<String>,
+}
+
+/// Request details needed to render a subscription.
+struct SubscriptionRequest<'a> {
+    params: &'a HashMap<String, String>,
+    headers: &'a HeaderMap,
+    /// The config before any profile is applied
+    base_config: &'a AppConfig,
+    loaded_at: SystemTime,
+    snapshot: &'a ConfigSnapshot,
+    credentials: Credentials<'a>,
+    /// The `[[auth.tokens]]` entry the caller authenticated with
+    entry: Option<&'a TokenEntry>,
+    serves_tls: bool,
+}
+
+async fn serve_subscription(
+    mut app_config: AppConfig,
+    request: &SubscriptionRequest<'_>,
+    upstream: &Arc<UpstreamCache>,
+) -> Response<String> {
+    // Apply per-request provider overrides from the query string
+    let overrides = SubscriptionOverrides::from_query(request.params);
+    // A signature covers only the path and expiry, so whoever holds a signed
+    // link must not be able to change the upstreams behind it
+    if matches!(request.credentials, Credentials::SignedLink { .. }) && !overrides.is_empty() {
+        return create_json_error_response(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "Signed links cannot override providers",
+        );
+    }
+    if let Err(err) = overrides.apply(&mut app_config) {
+        metrics().record_generation_error(&err);
+        return create_error_response(&err);
     }
 
+    // URLs from the query string are only handed back to the client, the
+    // server never fetches them
+    let mut proxies = app_config.proxies.clone();
+    proxies.retain(|name, _| !overrides.providers.contains_key(name));
+    let server = app_config.server.clone().unwrap_or_default();
+    let base_url = public_base_url(&app_config, request.headers, request.serves_tls);
     match generate_clash_config_with_validation(app_config) {
-        Ok(clash_config) => match serde_yaml::to_string(&clash_config) {
-            Ok(yaml) => Response::builder()
+        Ok(mut clash_config) => {
+            if server.relay_providers {
+                // Providers replaced by the caller already point at a URL they know
+                let skip = overrides.providers.keys().cloned().collect();
+                relay_proxy_providers(&mut clash_config, &skip, |name| {
+                    follow_up_url(&base_url, &["providers", name], request)
+                });
+            }
+            if server.mirror_rules {
+                // /rules/{name} serves the first rule set with that name, so
+                // only point at the mirror when the URL is the same one
+                let mirrored: HashMap<_, _> = collect_rule_sets(request.base_config)
+                    .into_iter()
+                    .map(|rule_set| (rule_set.name, rule_set.url))
+                    .collect();
+                relay_rule_providers(&mut clash_config, |name, url| {
+                    if mirrored.get(name).map(String::as_str) != Some(url) {
+                        return None;
+                    }
+                    follow_up_url(&base_url, &["rules", name], request)
+                });
+            }
+            render_subscription(&clash_config, &proxies, request, upstream).await
+        }
+        Err(mut err) => {
+            error!("Failed to generate clash config: {}", err);
+            metrics().record_generation_error(&err);
+            locate_config_error(&mut err, request.snapshot);
+            create_error_response(&err)
+        }
+    }
+}
+
+async fn render_subscription(
+    clash_config: &Config,
+    proxies: &HashMap<String, ProxySource>,
+    request: &SubscriptionRequest<'_>,
+    upstream: &Arc<UpstreamCache>,
+) -> Response<String> {
+    match serde_yaml::to_string(clash_config) {
+        Ok(yaml) => {
+            // Combine the traffic usage reported by every upstream provider
+            let userinfo = upstream.collect_userinfo(proxies).await;
+            let userinfo = SubscriptionUserinfo::aggregate(userinfo.values().flatten())
+                .map(|total| total.to_string());
+
+            // The usage header is part of what clients display, so it is
+            // hashed together with the body
+            let etag = compute_etag(&[
+                yaml.as_bytes(),
+                userinfo.as_deref().unwrap_or_default().as_bytes(),
+            ]);
+            let not_modified = request
+                .headers
+                .get(IF_NONE_MATCH)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| etag_matches(value, &etag));
+
+            let mut response = Response::builder()
+                .header(ETAG, &etag)
+                .header(LAST_MODIFIED, http_date(request.loaded_at))
+                .extension(AuditDetails {
+                    config_hash: Some(content_hash(&[yaml.as_bytes()])),
+                    ..Default::default()
+                });
+            if let Some(userinfo) = userinfo {
+                response = response.header(SUBSCRIPTION_USERINFO_HEADER, userinfo);
+            }
+
+            if not_modified {
+                return response
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(String::new())
+                    .unwrap();
+            }
+
+            response
                 .status(StatusCode::OK)
                 .header("content-type", "text/yaml; charset=utf-8")
                 .header("content-disposition", "attachment; filename=sub.yaml")
                 .body(yaml)
-                .unwrap(),
-            Err(err) => {
-                error!("Failed to serialize clash config: {}", err);
-                Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body("Failed to serialize clash config".to_string())
-                    .unwrap()
+                .unwrap()
+        }
+        Err(err) => {
+            error!("Failed to serialize clash config: {}", err);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Failed to serialize clash config".to_string())
+                .unwrap()
+        }
+    }
+}
+
+async fn relay_provider(
+    Extension(store): Extension<Arc<ConfigStore>>,
+    Extension(upstream): Extension<Arc<UpstreamCache>>,
+    Extension(limiter): Extension<Arc<RateLimiter>>,
+    Path(name): Path<String>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let app_config = store.current();
+
+    // Check token authentication
+    let credentials = request_credentials(&headers, None, &query);
+    let path = format!("/providers/{name}");
+    let entry = match authorize(&app_config, &limiter, &path, credentials).await {
+        Ok(entry) => entry,
+        Err(response) => return response.into_response(),
+    };
+
+    // The token's overrides decide which upstream a provider name maps to
+    let mut proxies = app_config.proxies.clone();
+    if let Some(overrides) = entry.and_then(|entry| entry.overrides.as_ref()) {
+        let mut token_config = AppConfig::clone(&app_config);
+        if let Err(err) = overrides.apply(&mut token_config) {
+            return create_error_response(&err).into_response();
+        }
+        proxies = token_config.proxies;
+    }
+
+    let relay_enabled = app_config
+        .server
+        .as_ref()
+        .is_none_or(|server| server.relay_providers);
+    let Some(request) = proxies
+        .get(&name)
+        .map(UpstreamRequest::from)
+        .filter(|_| relay_enabled)
+    else {
+        return create_json_error_response(
+            StatusCode::NOT_FOUND,
+            "provider_not_found",
+            &format!("Provider '{name}' does not exist"),
+        )
+        .into_response();
+    };
+
+    match upstream.provider(&request).await {
+        Ok(content) => {
+            let mut response = Response::builder().status(StatusCode::OK).header(
+                CONTENT_TYPE,
+                content
+                    .content_type
+                    .as_deref()
+                    .unwrap_or("text/plain; charset=utf-8"),
+            );
+            if let Some(userinfo) = content.userinfo {
+                response = response.header(SUBSCRIPTION_USERINFO_HEADER, userinfo.to_string());
             }
-        },
+            response.body(Body::from(content.body)).unwrap()
+        }
         Err(err) => {
-            error!("Failed to generate clash config: {}", err);
-            create_error_response(&err)
+            error!("Failed to fetch provider {}: {}", name, err);
+            create_json_error_response(
+                StatusCode::BAD_GATEWAY,
+                "upstream_unavailable",
+                &format!("Failed to fetch provider '{name}' from upstream"),
+            )
+            .into_response()
+        }
+    }
+}
+
+async fn mirror_rule_set(
+    Extension(store): Extension<Arc<ConfigStore>>,
+    Extension(mirror): Extension<Arc<RuleMirror>>,
+    Extension(limiter): Extension<Arc<RateLimiter>>,
+    Path(name): Path<String>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let app_config = store.current();
+
+    // Check token authentication
+    let credentials = request_credentials(&headers, None, &query);
+    let path = format!("/rules/{name}");
+    let entry = match authorize(&app_config, &limiter, &path, credentials).await {
+        Ok(entry) => entry,
+        Err(response) => return response.into_response(),
+    };
+
+    let server = app_config.server.clone().unwrap_or_default();
+    let rule_set = collect_rule_sets(&app_config)
+        .into_iter()
+        .find(|rule_set| rule_set.name == name)
+        .filter(|_| server.mirror_rules);
+    let Some(rule_set) = rule_set else {
+        return create_json_error_response(
+            StatusCode::NOT_FOUND,
+            "rule_set_not_found",
+            &format!("Rule set '{name}' does not exist"),
+        )
+        .into_response();
+    };
+
+    // Tokens bound to a profile may only fetch the rule sets of that profile
+    if let Some(profile) = entry.and_then(|entry| entry.profile.as_deref()) {
+        let allowed = app_config.with_profile(profile).is_some_and(|config| {
+            config.rules.iter().any(|rule| {
+                matches!(rule, RuleCfg::Set(set) if set.name == rule_set.name && set.url == rule_set.url)
+            })
+        });
+        if !allowed {
+            return create_json_error_response(
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                &format!("Token is not allowed to access rule set '{name}'"),
+            )
+            .into_response();
+        }
+    }
+
+    match mirror.get(&server.rule_cache_dir(), &rule_set).await {
+        Ok(mirrored) => {
+            let etag = compute_etag(&[&mirrored.body]);
+            let not_modified = headers
+                .get(IF_NONE_MATCH)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| etag_matches(value, &etag));
+
+            let response = Response::builder()
+                .header(ETAG, &etag)
+                .header(LAST_MODIFIED, http_date(mirrored.modified))
+                .header(
+                    CACHE_CONTROL,
+                    format!("max-age={}", get_rule_set_update_interval(&rule_set)),
+                );
+            if not_modified {
+                return response
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(Body::empty())
+                    .unwrap();
+            }
+            response
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+                .body(Body::from(mirrored.body))
+                .unwrap()
+        }
+        Err(err) => {
+            error!("Failed to mirror rule set {}: {}", name, err);
+            create_json_error_response(
+                StatusCode::BAD_GATEWAY,
+                "upstream_unavailable",
+                &format!("Failed to fetch rule set '{name}' from upstream"),
+            )
+            .into_response()
+        }
+    }
+}
+
+/// Serves the JSON Schema of the config file; it holds no secrets, so it is
+/// public like the health checks.
+async fn get_schema() -> Response<String> {
+    create_json_response(StatusCode::OK, &AppConfig::json_schema())
+}
+
+async fn healthz() -> Response<String> {
+    create_json_response(StatusCode::OK, &serde_json::json!({ "status": "ok" }))
+}
+
+/// Reports whether the current config can be turned into a subscription.
+async fn readyz(Extension(store): Extension<Arc<ConfigStore>>) -> Response<String> {
+    // Every profile is checked along with the base config when it is loaded
+    match store.snapshot().check().clone().into_result() {
+        Ok(_) => create_json_response(StatusCode::OK, &serde_json::json!({ "status": "ready" })),
+        Err(err) => {
+            error!("Readiness check failed: {}", err);
+            create_json_error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                err.kind(),
+                &redactor().redact(&err.to_string()),
+            )
         }
     }
 }
 
+async fn get_metrics() -> impl IntoResponse {
+    ([(CONTENT_TYPE, METRICS_CONTENT_TYPE)], metrics().encode())
+}
+
+/// Records request count and latency for every matched route.
+async fn track_metrics(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    metrics().observe_request(&route, response.status().as_u16(), start.elapsed());
+    response
+}
+
+/// Applies `[server.rate-limit]` to the authenticated routes and locks out
+/// addresses that keep failing authentication.
+async fn rate_limit(
+    Extension(store): Extension<Arc<ConfigStore>>,
+    Extension(limiter): Extension<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let app_config = store.current();
+    let Some(server) = app_config.server.as_ref() else {
+        return next.run(request).await;
+    };
+    let Some(config) = server.rate_limit.as_ref() else {
+        return next.run(request).await;
+    };
+
+    // Tokens are limited in `authorize` once they are known to be valid.
+    // Clients without an address share one bucket so the unix socket is
+    // still subject to the limits.
+    let client =
+        client_ip(&request, server.trust_forwarded_for).map_or(ClientAddr::Unix, ClientAddr::Ip);
+    if let Err(err) = limiter.check(config, Some(client), None) {
+        metrics().record_rate_limited(err.reason());
+        return create_rate_limited_response(&err).into_response();
+    }
+
+    let response = next.run(request).await;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        limiter.record_failure(config, client);
+    }
+    response
+}
+
+/// Appends a line per request to `[server.audit-log]`.
+async fn audit_request(
+    Extension(store): Extension<Arc<ConfigStore>>,
+    Extension(audit_log): Extension<AuditQueue>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let app_config = store.current();
+    let Some(server) = app_config.server.as_ref() else {
+        return next.run(request).await;
+    };
+    let Some(config) = server.audit_log.as_ref() else {
+        return next.run(request).await;
+    };
+
+    let ip = client_ip(&request, server.trust_forwarded_for);
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+
+    let response = next.run(request).await;
+    let details = response
+        .extensions()
+        .get::<AuditDetails>()
+        .cloned()
+        .unwrap_or_default();
+    let record = AuditRecord {
+        timestamp: SystemTime::now(),
+        ip,
+        user_agent,
+        route,
+        token_label: details.token_label,
+        profile: details.profile,
+        status: response.status().as_u16(),
+        config_hash: details.config_hash,
+    };
+    audit_log.record(config.clone(), record).await;
+    response
+}
+
+/// Returns the address of the client, taken from the last `X-Forwarded-For`
+/// entry when the reverse proxy in front of this service is trusted. Clients
+/// connecting over a unix socket have no address otherwise.
+fn client_ip(request: &Request, trust_forwarded_for: bool) -> Option<IpAddr> {
+    if trust_forwarded_for
+        && let Some(ip) = request
+            .headers()
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .next_back()
+            .and_then(|entry| entry.trim().parse::<IpAddr>().ok())
+    {
+        return Some(ip);
+    }
+    request
+        .extensions()
+        .get::<ConnectInfo<RemoteAddr>>()
+        .and_then(|ConnectInfo(RemoteAddr(addr))| addr.map(|addr| addr.ip()))
+}
+
+/// Drops rate limit state that no longer affects any client.
+async fn prune_rate_limiter(store: Arc<ConfigStore>, limiter: Arc<RateLimiter>) {
+    let mut ticker = tokio::time::interval(RATE_LIMIT_PRUNE_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        let app_config = store.current();
+        if let Some(config) = app_config
+            .server
+            .as_ref()
+            .and_then(|server| server.rate_limit.as_ref())
+        {
+            limiter.prune(config);
+        }
+    }
+}
+
+/// Drops cached upstream responses that have expired.
+async fn prune_upstream_cache(upstream: Arc<UpstreamCache>) {
+    let mut ticker = tokio::time::interval(UPSTREAM_PRUNE_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        upstream.prune();
+    }
+}
+
+/// Returns the externally reachable base URL of this service.
+fn public_base_url(app_config: &AppConfig, headers: &HeaderMap, serves_tls: bool) -> String {
+    if let Some(public_url) = app_config
+        .server
+        .as_ref()
+        .and_then(|server| server.public_url.clone())
+    {
+        return public_url;
+    }
+
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    let default_scheme = if serves_tls { "https" } else { "http" };
+    // Forwarded headers can be set by any client, only a trusted proxy may
+    // override the scheme and host
+    let trust_forwarded = app_config
+        .server
+        .as_ref()
+        .is_some_and(|server| server.trust_forwarded_for);
+    let forwarded = |name: &str| header(name).filter(|_| trust_forwarded);
+    let scheme = forwarded("x-forwarded-proto").unwrap_or(default_scheme);
+    let host = forwarded("x-forwarded-host")
+        .or_else(|| header("host"))
+        .unwrap_or("localhost");
+    format!("{scheme}://{host}")
+}
+
 async fn get_config_info(
-    Extension(app_config): Extension<AppConfig>,
+    Extension(store): Extension<Arc<ConfigStore>>,
+    Extension(upstream): Extension<Arc<UpstreamCache>>,
+    Extension(limiter): Extension<Arc<RateLimiter>>,
     Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
 ) -> Response<String> {
+    let base_config = store.current();
+
     // Check token authentication
-    if let Err(response) = validate_token(&app_config, &query) {
-        return response;
-    }
+    let credentials = request_credentials(&headers, None, &query);
+    let entry = match authorize(&base_config, &limiter, "/config", credentials).await {
+        Ok(entry) => entry,
+        Err(response) => return response,
+    };
+    // Describe what the token is actually served
+    let app_config = match resolve_token_config(&base_config, None, entry) {
+        Ok(app_config) => app_config,
+        Err(response) => return *response,
+    };
+    let bound_profile = entry.and_then(|entry| entry.profile.as_ref());
 
     use serde_json::json;
 
     let region_groups = get_available_region_groups(&app_config);
     let proxy_providers: Vec<String> = app_config.proxies.keys().cloned().collect();
+    let mut profiles: Vec<&String> = match bound_profile {
+        Some(profile) => vec![profile],
+        None => base_config.profiles.keys().collect(),
+    };
+    profiles.sort();
+    let userinfo = upstream.collect_userinfo(&app_config.proxies).await;
+    let userinfo_total = SubscriptionUserinfo::aggregate(userinfo.values().flatten());
 
     let info = json!({
         "proxy_providers": proxy_providers,
@@ -81,6 +902,11 @@ async fn get_config_info(
             sub_util::ProxyGroup::Relay(r) => &r.common.name,
         }).collect::<Vec<_>>(),
         "rules_count": app_config.rules.len(),
+        "profiles": profiles,
+        "subscription_userinfo": {
+            "total": userinfo_total,
+            "providers": userinfo,
+        },
         "region_groups_enabled": app_config.region_groups.as_ref().map(|r| r.enabled).unwrap_or(false)
     });
 
@@ -88,6 +914,11 @@ async fn get_config_info(
         Ok(json) => Response::builder()
             .status(StatusCode::OK)
             .header("content-type", "application/json")
+            .extension(AuditDetails {
+                token_label: entry.map(|entry| entry.label.clone()),
+                profile: bound_profile.cloned(),
+                config_hash: Some(content_hash(&[json.as_bytes()])),
+            })
             .body(json)
             .unwrap(),
         Err(err) => {
@@ -102,6 +933,12 @@ async fn get_config_info(
 
 #[tokio::main]
 async fn main() {
+    let args = Args::parse();
+    if let Some(command) = args.command {
+        run_command(command, &args.config, args.config_format);
+        return;
+    }
+
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
@@ -114,24 +951,81 @@ async fn main() {
                 .into()
             }),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(
+            tracing_subscriber::fmt::layer().with_writer(RedactingMakeWriter::new(std::io::stdout)),
+        )
         .init();
 
-    let args = Args::parse();
-    let app_config = match AppConfig::load_from_file(args.config) {
-        Ok(config) => config,
+    redactor().set_enabled(!args.show_secrets);
+    if args.show_secrets {
+        warn!("--show-secrets is set, upstream URLs and tokens will appear in logs and responses");
+    }
+
+    let store = match ConfigStore::load_with_format(&args.config, args.config_format) {
+        Ok(store) => Arc::new(store),
         Err(err) => {
             error!("Failed to load config: {}", err);
             exit(1);
         }
     };
-    let router = Router::new()
+    redactor().set_secrets(&store.current());
+    // The service starts anyway so that requests report the problems too
+    for diagnostic in &store.check() {
+        match diagnostic.severity {
+            Severity::Error => error!("{}", diagnostic),
+            Severity::Warning => warn!("{}", diagnostic),
+        }
+    }
+    let tls = tls_certificates(&args, &store.current()).map(|certificates| {
+        tokio::spawn(reload_tls_certificates(certificates.clone()));
+        certificates.server_config()
+    });
+
+    tokio::spawn(watch_config(store.clone(), args.watch_interval));
+    let upstream = Arc::new(UpstreamCache::new());
+    tokio::spawn(prune_upstream_cache(upstream.clone()));
+    let mirror = Arc::new(RuleMirror::new());
+    tokio::spawn(refresh_rule_mirror(store.clone(), mirror.clone()));
+    let limiter = Arc::new(RateLimiter::new());
+    tokio::spawn(prune_rate_limiter(store.clone(), limiter.clone()));
+
+    let audit_log = match AuditQueue::spawn(AuditLog::new(), AUDIT_QUEUE_CAPACITY) {
+        Ok(audit_log) => audit_log,
+        Err(err) => {
+            error!("Failed to start audit log writer: {}", err);
+            exit(1);
+        }
+    };
+
+    // Routes that check credentials are rate limited, and the ones that hand
+    // out configs are audited, including rejected requests
+    let audited = Router::new()
         .route("/subs", get(hello_world))
+        .route("/subs/{profile}", get(profile_subs))
+        .route("/subs/{profile}/{token}", get(profile_token_subs))
         .route("/config", get(get_config_info))
-        .layer(Extension(app_config));
+        .route_layer(middleware::from_fn(rate_limit))
+        .route_layer(middleware::from_fn(audit_request));
+    let authenticated = Router::new()
+        .route("/providers/{name}", get(relay_provider))
+        .route("/rules/{name}", get(mirror_rule_set))
+        .route_layer(middleware::from_fn(rate_limit))
+        .merge(audited);
 
-    tracing::info!("Server is running on http://{}", &args.bind);
-    let listener = match tokio::net::TcpListener::bind(&args.bind).await {
+    let router = authenticated
+        .route("/metrics", get(get_metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/schema", get(get_schema))
+        .route_layer(middleware::from_fn(track_metrics))
+        .layer(Extension(store))
+        .layer(Extension(upstream))
+        .layer(Extension(mirror))
+        .layer(Extension(limiter))
+        .layer(Extension(ServesTls(tls.is_some())))
+        .layer(Extension(audit_log.clone()));
+
+    let listener = match listen(&args, tls).await {
         Ok(listener) => listener,
         Err(err) => {
             error!("Failed to bind to address: {}", err);
@@ -139,34 +1033,399 @@ async fn main() {
         }
     };
 
-    if let Err(err) = axum::serve(listener, router).await {
-        error!("Server error: {}", err);
-        exit(1);
+    let service = router.into_make_service_with_connect_info::<RemoteAddr>();
+    let (drain_tx, drain_rx) = tokio::sync::oneshot::channel();
+    let server = axum::serve(listener, service)
+        .with_graceful_shutdown(async {
+            let _ = drain_rx.await;
+        })
+        .into_future();
+    tokio::pin!(server);
+
+    let drained = tokio::select! {
+        result = &mut server => {
+            if let Err(err) = result {
+                error!("Server error: {}", err);
+            }
+            exit(1);
+        }
+        signal = shutdown_signal() => {
+            let timeout = Duration::from_secs(args.shutdown_timeout);
+            info!(
+                "Received {}, no longer accepting connections, draining in-flight requests for up to {}s",
+                signal,
+                timeout.as_secs()
+            );
+            let _ = drain_tx.send(());
+            tokio::time::timeout(timeout, &mut server).await.is_ok()
+        }
+    };
+
+    // Mirrored rule sets are written to disk as they are fetched and the
+    // upstream cache lives in memory, so only the queued audit records need
+    // writing out and syncing
+    if let Err(err) = audit_log.flush().await {
+        error!("Failed to flush audit log: {}", err);
+    }
+    if drained {
+        info!("All requests finished, shut down cleanly");
+    } else {
+        info!(
+            "Shutdown timeout of {}s elapsed, closed the remaining connections",
+            args.shutdown_timeout
+        );
     }
 }
-fn create_error_response(error: &ConfigError) -> Response<String> {
-    use serde_json::json;
 
-    let (status_code, error_type) = match error {
-        ConfigError::InvalidSubscriptionUrl(_) => {
-            (StatusCode::BAD_REQUEST, "invalid_subscription_url")
+fn run_command(
+    command: Command,
+    config_path: &std::path::Path,
+    config_format: Option<ConfigFormat>,
+) {
+    match command {
+        Command::HashToken { token, scheme } => {
+            let token = match token {
+                Some(token) => token,
+                None => {
+                    let mut line = String::new();
+                    if let Err(err) = std::io::stdin().read_line(&mut line) {
+                        eprintln!("Failed to read token from stdin: {err}");
+                        exit(1);
+                    }
+                    line.trim_end_matches(['\r', '\n']).to_string()
+                }
+            };
+            if token.is_empty() {
+                eprintln!("Token cannot be empty");
+                exit(1);
+            }
+            // Hashes are salted, so a reused token cannot be spotted in the
+            // config later; the config is optional here
+            if let Ok(app_config) =
+                AppConfig::load_from_file_with_format(config_path, config_format)
+                && let Some(warning) = token_reuse_warning(&app_config, &token)
+            {
+                eprintln!("Warning: {}: {warning}", config_path.display());
+            }
+            println!("{}", hash_token(&token, scheme));
+        }
+        Command::SignLink {
+            days,
+            profile,
+            base_url,
+        } => {
+            let app_config = match AppConfig::load_from_file_with_format(config_path, config_format)
+            {
+                Ok(app_config) => app_config,
+                Err(err) => {
+                    eprintln!("Failed to load config: {err}");
+                    exit(1);
+                }
+            };
+            let Some(signing_key) = app_config
+                .auth
+                .as_ref()
+                .and_then(|auth| auth.signing_key.as_deref())
+            else {
+                eprintln!("auth.signing-key is not set in {}", config_path.display());
+                exit(1);
+            };
+            if let Some(profile) = &profile
+                && !app_config.profiles.contains_key(profile)
+            {
+                eprintln!("Profile '{profile}' does not exist");
+                exit(1);
+            }
+            let Some(base_url) = base_url.or_else(|| {
+                app_config
+                    .server
+                    .as_ref()
+                    .and_then(|server| server.public_url.clone())
+            }) else {
+                eprintln!("--base-url is required when server.public-url is not set");
+                exit(1);
+            };
+
+            let expires = unix_now().saturating_add(days.saturating_mul(24 * 60 * 60));
+            let segments: Vec<&str> = std::iter::once("subs").chain(profile.as_deref()).collect();
+            let signature = sign_link(signing_key, &format!("/{}", segments.join("/")), expires);
+            match build_public_url(
+                &base_url,
+                &segments,
+                &[("exp", &expires.to_string()), ("sig", &signature)],
+            ) {
+                Some(url) => println!("{url}"),
+                None => {
+                    eprintln!("Invalid base URL: {base_url}");
+                    exit(1);
+                }
+            }
+        }
+        Command::Schema => {
+            let schema = AppConfig::json_schema();
+            println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+        }
+        Command::Check => {
+            let store = match ConfigStore::load_with_format(config_path, config_format) {
+                Ok(store) => store,
+                Err(err) => {
+                    eprintln!("Failed to load config: {err}");
+                    exit(1);
+                }
+            };
+            let diagnostics = store.check();
+            if diagnostics.is_empty() {
+                println!("{} is valid", config_path.display());
+                return;
+            }
+            println!("{diagnostics}");
+            if diagnostics.has_errors() {
+                exit(1);
+            }
+        }
+    }
+}
+
+/// Takes over the socket passed by systemd, or binds `--bind`.
+/// Describes where `token` is already configured, so that `hash-token` can
+/// point out a reused token or the plaintext value the hash is meant to replace.
+fn token_reuse_warning(app_config: &AppConfig, token: &str) -> Option<String> {
+    let owner = token_owner(app_config, token)?;
+    let plaintext = app_config.auth.as_ref().is_some_and(|auth| {
+        auth.token.as_deref() == Some(token) || auth.tokens.iter().any(|entry| entry.token == token)
+    });
+    Some(if plaintext {
+        format!("token '{owner}' is stored in plain text, replace it with the hash below")
+    } else {
+        format!("token is already in use by '{owner}'")
+    })
+}
+
+async fn listen(args: &Args, tls: Option<Arc<ServerConfig>>) -> std::io::Result<ServerListener> {
+    let scheme = if tls.is_some() { "https" } else { "http" };
+
+    #[cfg(unix)]
+    {
+        if let Some(listener) = ServerListener::from_systemd(tls.clone())? {
+            info!("Server is running on the socket passed by systemd");
+            return Ok(listener);
+        }
+        if let Some(path) = args.bind.strip_prefix("unix:") {
+            if tls.is_some() {
+                return Err(std::io::Error::other(
+                    "TLS is not supported on unix sockets",
+                ));
+            }
+            let listener = ServerListener::bind_unix(std::path::Path::new(path), args.socket_mode)?;
+            info!("Server is running on {}", &args.bind);
+            return Ok(listener);
         }
-        ConfigError::ProxyGroupGenerationFailed(_) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "proxy_group_generation_failed",
-        ),
-        ConfigError::RuleProcessingFailed(_) => (StatusCode::BAD_REQUEST, "rule_processing_failed"),
-        ConfigError::ConfigValidationFailed(_) => {
-            (StatusCode::BAD_REQUEST, "config_validation_failed")
+    }
+
+    let listener = ServerListener::bind(&args.bind, tls).await?;
+    info!("Server is running on {}://{}", scheme, &args.bind);
+    Ok(listener)
+}
+
+fn parse_socket_mode(value: &str) -> Result<u32, String> {
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("invalid octal mode '{value}', expected e.g. 660")),
+    }
+}
+
+/// Loads the certificates given on the command line or in `[server.tls]`,
+/// exiting when they cannot be used.
+fn tls_certificates(args: &Args, app_config: &AppConfig) -> Option<Arc<TlsCertificates>> {
+    let (cert, key) = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => (cert.clone(), key.clone()),
+        _ => {
+            let tls = app_config.server.as_ref()?.tls.as_ref()?;
+            (tls.cert.clone(), tls.key.clone())
         }
     };
+    match TlsCertificates::load(&cert, &key) {
+        Ok(certificates) => Some(Arc::new(certificates)),
+        Err(err) => {
+            error!("Failed to load TLS certificate: {}", err);
+            exit(1);
+        }
+    }
+}
 
-    let error_response = json!({
+/// Picks up renewed certificates without restarting.
+async fn reload_tls_certificates(certificates: Arc<TlsCertificates>) {
+    let mut ticker = tokio::time::interval(TLS_RELOAD_CHECK_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        match certificates.reload_if_changed() {
+            Ok(true) => info!("Reloaded TLS certificate"),
+            Ok(false) => {}
+            Err(err) => error!("{}, keeping the previous TLS certificate", err),
+        }
+    }
+}
+
+/// Reloads the config when the file changes or the process receives SIGHUP.
+async fn watch_config(store: Arc<ConfigStore>, interval_secs: u64) {
+    let mut ticker = (interval_secs > 0).then(|| {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker
+    });
+    let mut hangup = hangup_signal();
+
+    loop {
+        let result = tokio::select! {
+            _ = async { ticker.as_mut().unwrap().tick().await }, if ticker.is_some() => {
+                store.reload_if_changed()
+            }
+            Some(()) = hangup.recv() => {
+                info!("Received SIGHUP, reloading config");
+                store.reload().map(Some)
+            }
+            else => return,
+        };
+
+        match result {
+            Ok(Some(config)) => {
+                redactor().set_secrets(&config);
+                info!("Reloaded config from {}", store.path().display());
+            }
+            Ok(None) => {}
+            Err(err) => error!("{}, keeping the previous config", err),
+        }
+    }
+}
+
+/// Keeps the mirrored rule sets up to date with their upstream.
+async fn refresh_rule_mirror(store: Arc<ConfigStore>, mirror: Arc<RuleMirror>) {
+    let mut ticker = tokio::time::interval(RULE_MIRROR_CHECK_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        let app_config = store.current();
+        let Some(server) = app_config
+            .server
+            .as_ref()
+            .filter(|server| server.mirror_rules)
+        else {
+            continue;
+        };
+        let rule_sets = collect_rule_sets(&app_config);
+        for (name, err) in mirror
+            .refresh_due(&server.rule_cache_dir(), &rule_sets)
+            .await
+        {
+            error!("Failed to refresh rule set {}: {}", name, err);
+        }
+    }
+}
+
+/// Resolves with the name of the signal once the process is asked to stop.
+#[cfg(unix)]
+async fn shutdown_signal() -> &'static str {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => Some(terminate),
+        Err(err) => {
+            error!("Failed to install SIGTERM handler: {}", err);
+            None
+        }
+    };
+    tokio::select! {
+        _ = async { terminate.as_mut().unwrap().recv().await }, if terminate.is_some() => "SIGTERM",
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "Ctrl-C"
+}
+
+#[cfg(unix)]
+fn hangup_signal() -> tokio::sync::mpsc::Receiver<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    match signal(SignalKind::hangup()) {
+        Ok(mut hangup) => {
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    if tx.send(()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        Err(err) => error!("Failed to install SIGHUP handler: {}", err),
+    }
+    rx
+}
+
+#[cfg(not(unix))]
+fn hangup_signal() -> tokio::sync::mpsc::Receiver<()> {
+    tokio::sync::mpsc::channel(1).1
+}
+
+/// Adds file locations to the problems the loaded config reports as well.
+/// Problems introduced by a profile or by overrides keep no location, since
+/// the files do not say where they come from.
+fn locate_config_error(error: &mut ConfigError, snapshot: &ConfigSnapshot) {
+    let ConfigError::Invalid(diagnostics) = error else {
+        return;
+    };
+    let located = snapshot.check();
+    for diagnostic in diagnostics.iter_mut() {
+        diagnostic.location = located
+            .iter()
+            .find(|other| other.path == diagnostic.path && other.message == diagnostic.message)
+            .and_then(|other| other.location.clone());
+    }
+}
+
+fn create_error_response(error: &ConfigError) -> Response<String> {
+    use serde_json::json;
+
+    let status_code = match error {
+        ConfigError::ProxyGroupGenerationFailed(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        ConfigError::InvalidSubscriptionUrl(_)
+        | ConfigError::RuleProcessingFailed(_)
+        | ConfigError::ConfigValidationFailed(_)
+        | ConfigError::Invalid(_) => StatusCode::BAD_REQUEST,
+    };
+
+    let mut error_response = json!({
         "error": {
-            "type": error_type,
-            "message": error.to_string()
+            "type": error.kind(),
+            "message": redactor().redact(&error.to_string())
         }
     });
+    if let Some(diagnostics) = error.diagnostics() {
+        error_response["error"]["diagnostics"] = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let mut entry = json!({
+                    "severity": diagnostic.severity.as_str(),
+                    "type": diagnostic.kind,
+                    "path": diagnostic.path,
+                    "message": redactor().redact(&diagnostic.message),
+                });
+                if let Some(location) = &diagnostic.location {
+                    entry["line"] = location.line.into();
+                    entry["column"] = location.column.into();
+                }
+                entry
+            })
+            .collect();
+    }
 
     Response::builder()
         .status(status_code)
@@ -175,45 +1434,572 @@ fn create_error_response(error: &ConfigError) -> Response<String> {
         .unwrap()
 }
 
-fn validate_token(app_config: &AppConfig, query: &TokenQuery) -> Result<(), Response<String>> {
-    // Check if authentication is enabled
-    if let Some(auth_config) = &app_config.auth {
-        if auth_config.enabled {
-            // Authentication is enabled, check for token
-            let provided_token = query.token.as_deref().unwrap_or("");
-            let expected_token = auth_config.token.as_deref().unwrap_or("");
-            
-            if expected_token.is_empty() {
-                error!("Authentication is enabled but no token is configured");
-                return Err(create_unauthorized_response("Server configuration error"));
-            }
-            
-            if provided_token.is_empty() {
-                return Err(create_unauthorized_response("Token required"));
-            }
-            
-            if provided_token != expected_token {
-                return Err(create_unauthorized_response("Invalid token"));
-            }
+/// Picks the token from the `Authorization` header, the path or the query
+/// string, in that order, falling back to a signed link.
+fn request_credentials<'a>(
+    headers: &'a HeaderMap,
+    path_token: Option<&'a str>,
+    query: &'a TokenQuery,
+) -> Credentials<'a> {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(bearer_token)
+        .or(path_token)
+        .or(query.token.as_deref())
+        .filter(|token| !token.is_empty());
+    match (token, query.sig.as_deref()) {
+        (Some(token), _) => Credentials::Token(token),
+        (None, Some(signature)) => Credentials::SignedLink {
+            expires: query.exp.as_deref(),
+            signature,
+        },
+        (None, None) => Credentials::None,
+    }
+}
+
+/// Validates the credentials for `path`, records rejected requests and
+/// applies the per-token rate limit to the ones that pass.
+async fn authorize<'a>(
+    app_config: &'a Arc<AppConfig>,
+    limiter: &RateLimiter,
+    path: &str,
+    credentials: Credentials<'_>,
+) -> Result<Option<&'a TokenEntry>, Response<String>> {
+    let result = match credentials {
+        Credentials::None => validate_token(app_config, None),
+        Credentials::Token(token) => check_token(app_config, token).await,
+        Credentials::SignedLink { expires, signature } => {
+            validate_signed_link(app_config, path, expires, signature).map(|()| None)
+        }
+    };
+    let entry = result.map_err(|err| {
+        metrics().record_auth_failure(err.reason());
+        create_unauthorized_response(&err.to_string())
+    })?;
+
+    // Only valid credentials get a bucket, so guesses cannot fill the limiter;
+    // failed attempts count against the address instead
+    let key = match (credentials, entry) {
+        (_, Some(entry)) => Some(format!("token:{}", entry.label)),
+        (Credentials::Token(_), None) => Some("auth.token".to_string()),
+        (Credentials::SignedLink { signature, .. }, None) => Some(format!("link:{signature}")),
+        (Credentials::None, None) => None,
+    };
+    let config = app_config
+        .server
+        .as_ref()
+        .and_then(|server| server.rate_limit.as_ref());
+    if let (Some(config), Some(key)) = (config, key.filter(|_| is_auth_enabled(app_config)))
+        && let Err(err) = limiter.check(config, None, Some(&key))
+    {
+        metrics().record_rate_limited(err.reason());
+        return Err(create_rate_limited_response(&err));
+    }
+    Ok(entry)
+}
+
+/// Runs `validate_token` on the blocking pool, since tokens stored as argon2
+/// hashes take a noticeable amount of CPU to verify.
+async fn check_token<'a>(
+    app_config: &'a Arc<AppConfig>,
+    token: &str,
+) -> Result<Option<&'a TokenEntry>, AuthError> {
+    let config = Arc::clone(app_config);
+    let token = token.to_string();
+    let index = tokio::task::spawn_blocking(move || {
+        let entry = validate_token(&config, Some(&token))?;
+        Ok(entry.and_then(|entry| {
+            let tokens = &config.auth.as_ref()?.tokens;
+            tokens.iter().position(|other| std::ptr::eq(other, entry))
+        }))
+    })
+    .await
+    .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))?;
+
+    let tokens = app_config.auth.as_ref().map(|auth| auth.tokens.as_slice());
+    Ok(index.and_then(|index| tokens?.get(index)))
+}
+
+/// Builds a URL to another endpoint of this service that the client can
+/// fetch with the same credentials it used for the subscription.
+fn follow_up_url(
+    base_url: &str,
+    segments: &[&str],
+    request: &SubscriptionRequest<'_>,
+) -> Option<RelayTarget> {
+    let signing_key = request
+        .base_config
+        .auth
+        .as_ref()
+        .and_then(|auth| auth.signing_key.as_deref());
+    match request.credentials {
+        // Tokens stay out of URLs, and so out of access logs, unless the
+        // client put the token in the query string itself
+        Credentials::Token(token) if request.params.get("token").is_some_and(|t| t == token) => {
+            build_public_url(base_url, segments, &[("token", token)]).map(RelayTarget::from)
+        }
+        Credentials::Token(token) => Some(RelayTarget {
+            url: build_public_url(base_url, segments, &[])?,
+            header: BTreeMap::from([(
+                "Authorization".to_string(),
+                vec![format!("Bearer {token}")],
+            )]),
+        }),
+        // Each endpoint gets its own signature with the same expiry
+        Credentials::SignedLink {
+            expires: Some(expires),
+            ..
+        } if is_auth_enabled(request.base_config) => {
+            let path = format!("/{}", segments.join("/"));
+            let signature = sign_link(signing_key?, &path, expires.parse().ok()?);
+            build_public_url(base_url, segments, &[("exp", expires), ("sig", &signature)])
+                .map(RelayTarget::from)
         }
+        _ => build_public_url(base_url, segments, &[]).map(RelayTarget::from),
     }
-    // If authentication is not configured or disabled, allow access
-    Ok(())
 }
 
 fn create_unauthorized_response(message: &str) -> Response<String> {
+    create_json_error_response(StatusCode::UNAUTHORIZED, "unauthorized", message)
+}
+
+fn create_rate_limited_response(error: &RateLimitError) -> Response<String> {
+    let mut response = create_json_error_response(
+        StatusCode::TOO_MANY_REQUESTS,
+        "rate_limited",
+        &error.to_string(),
+    );
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, error.retry_after_secs().into());
+    response
+}
+
+fn create_profile_not_found_response(profile: &str) -> Response<String> {
+    create_json_error_response(
+        StatusCode::NOT_FOUND,
+        "profile_not_found",
+        &format!("Profile '{profile}' does not exist"),
+    )
+}
+
+fn create_json_error_response(
+    status_code: StatusCode,
+    error_type: &str,
+    message: &str,
+) -> Response<String> {
     use serde_json::json;
-    
+
     let error_response = json!({
         "error": {
-            "type": "unauthorized",
-            "message": message
+            "type": error_type,
+            "message": redactor().redact(message)
         }
     });
 
+    create_json_response(status_code, &error_response)
+}
+
+fn create_json_response(status_code: StatusCode, body: &serde_json::Value) -> Response<String> {
     Response::builder()
-        .status(StatusCode::UNAUTHORIZED)
+        .status(status_code)
         .header("content-type", "application/json")
-        .body(error_response.to_string())
+        .body(body.to_string())
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn store_with(content: &str) -> (NamedTempFile, Arc<ConfigStore>) {
+        let mut file = NamedTempFile::with_suffix(".toml").unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        let store = Arc::new(ConfigStore::load(file.path()).unwrap());
+        (file, store)
+    }
+
+    fn json_body(response: &Response<String>) -> serde_json::Value {
+        serde_json::from_str(response.body()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_healthz() {
+        let response = healthz().await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(&response)["status"], "ok");
+    }
+
+    #[tokio::test]
+    async fn test_readyz_valid_config() {
+        let (_file, store) = store_with(
+            r#"
+[proxies]
+test = "https://example.com/clash"
+"#,
+        );
+        let response = readyz(Extension(store)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(&response)["status"], "ready");
+    }
+
+    #[tokio::test]
+    async fn test_signed_link_rejects_overrides() {
+        let (_file, store) = store_with(
+            r#"
+[proxies]
+test = "https://example.com/clash"
+
+[auth]
+enabled = true
+signing-key = "0123456789abcdef"
+"#,
+        );
+        let snapshot = store.snapshot();
+        let upstream = Arc::new(UpstreamCache::new());
+        let limiter = RateLimiter::new();
+        let expires = (unix_now() + 3600).to_string();
+        let signature = sign_link("0123456789abcdef", "/subs", expires.parse().unwrap());
+        let credentials = Credentials::SignedLink {
+            expires: Some(&expires),
+            signature: &signature,
+        };
+
+        for (key, value) in [
+            ("only", "test"),
+            ("provider.test", "https://evil.example.com"),
+        ] {
+            let params = HashMap::from([(key.to_string(), value.to_string())]);
+            let response = serve_profile(
+                &snapshot,
+                &upstream,
+                &limiter,
+                None,
+                credentials,
+                &params,
+                &HeaderMap::new(),
+                false,
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            assert_eq!(json_body(&response)["error"]["type"], "forbidden");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authorize_limits_valid_tokens_only() {
+        let (_file, store) = store_with(
+            r#"
+[proxies]
+test = "https://example.com/clash"
+
+[auth]
+enabled = true
+
+[[auth.tokens]]
+label = "alice"
+token = "alice-token"
+
+[server.rate-limit]
+per-token = 1
+"#,
+        );
+        let app_config = store.current();
+        let limiter = RateLimiter::new();
+        let status = |result: Result<Option<&TokenEntry>, Response<String>>| {
+            result.map_or_else(|response| response.status(), |_| StatusCode::OK)
+        };
+
+        for _ in 0..3 {
+            let result = authorize(
+                &app_config,
+                &limiter,
+                "/config",
+                Credentials::Token("guess"),
+            )
+            .await;
+            assert_eq!(status(result), StatusCode::UNAUTHORIZED);
+        }
+        let result = authorize(
+            &app_config,
+            &limiter,
+            "/config",
+            Credentials::Token("alice-token"),
+        )
+        .await;
+        assert_eq!(status(result), StatusCode::OK);
+        let result = authorize(
+            &app_config,
+            &limiter,
+            "/config",
+            Credentials::Token("alice-token"),
+        )
+        .await;
+        assert_eq!(status(result), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_readyz_invalid_config() {
+        let (_file, store) = store_with(
+            r#"
+[proxies]
+test = "not-a-url"
+"#,
+        );
+        let response = readyz(Extension(store)).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = json_body(&response);
+        assert_eq!(body["error"]["type"], "invalid_subscription_url");
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("not-a-url")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_readyz_invalid_profile() {
+        let (_file, store) = store_with(
+            r#"
+[proxies]
+test = "https://example.com/clash"
+
+[[profiles.router.rules]]
+type = "single"
+tag = "MATCH"
+target = "Missing"
+"#,
+        );
+        let response = readyz(Extension(store)).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let message = json_body(&response)["error"]["message"].to_string();
+        assert!(
+            message.contains("profiles.router.rules[0].target"),
+            "{message}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config_info_uses_token_profile() {
+        let (_file, store) = store_with(
+            r#"
+[proxies]
+a = "http://127.0.0.1:1/a"
+b = "http://127.0.0.1:1/b"
+
+[[groups]]
+name = "Base"
+type = "select"
+use = ["a", "b"]
+
+[auth]
+enabled = true
+token = "shared-secret"
+
+[[auth.tokens]]
+label = "router"
+token = "router-secret"
+profile = "router"
+overrides = { only = ["a"] }
+
+[profiles.router]
+
+[[profiles.router.groups]]
+name = "Router"
+type = "select"
+use = ["a"]
+
+[profiles.phone]
+"#,
+        );
+        let upstream = Arc::new(UpstreamCache::new());
+        let limiter = Arc::new(RateLimiter::new());
+        let config_info = |token: &str| {
+            let query = TokenQuery {
+                token: Some(token.to_string()),
+                sig: None,
+                exp: None,
+            };
+            get_config_info(
+                Extension(store.clone()),
+                Extension(upstream.clone()),
+                Extension(limiter.clone()),
+                Query(query),
+                HeaderMap::new(),
+            )
+        };
+
+        let response = config_info("router-secret").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(&response);
+        assert_eq!(body["user_groups"], serde_json::json!(["Router"]));
+        assert_eq!(body["proxy_providers"], serde_json::json!(["a"]));
+        assert_eq!(body["profiles"], serde_json::json!(["router"]));
+        let details = response.extensions().get::<AuditDetails>().unwrap();
+        assert_eq!(details.profile.as_deref(), Some("router"));
+
+        // 共享 token 看到的是基础配置
+        let body = json_body(&config_info("shared-secret").await);
+        assert_eq!(body["user_groups"], serde_json::json!(["Base"]));
+        assert_eq!(body["profiles"], serde_json::json!(["phone", "router"]));
+    }
+
+    #[tokio::test]
+    async fn test_rule_set_limited_to_token_profile() {
+        let (_file, store) = store_with(
+            r#"
+[proxies]
+test = "http://127.0.0.1:1/clash"
+
+[server]
+mirror-rules = true
+
+[auth]
+enabled = true
+token = "shared-secret"
+
+[[auth.tokens]]
+label = "router"
+token = "router-secret"
+profile = "router"
+
+[[rules]]
+name = "base"
+type = "set"
+url = "http://127.0.0.1:1/base.yaml"
+behavior = "domain"
+target = "DIRECT"
+
+[[profiles.router.rules]]
+name = "router"
+type = "set"
+url = "http://127.0.0.1:1/router.yaml"
+behavior = "domain"
+target = "DIRECT"
+"#,
+        );
+        let mirror = Arc::new(RuleMirror::new());
+        let limiter = Arc::new(RateLimiter::new());
+        let rule_set = |name: &str, token: &str| {
+            let query = TokenQuery {
+                token: Some(token.to_string()),
+                sig: None,
+                exp: None,
+            };
+            mirror_rule_set(
+                Extension(store.clone()),
+                Extension(mirror.clone()),
+                Extension(limiter.clone()),
+                Path(name.to_string()),
+                Query(query),
+                HeaderMap::new(),
+            )
+        };
+
+        let response = rule_set("base", "router-secret").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        // 允许访问的规则集会去上游获取，这里上游不可用
+        let response = rule_set("router", "router-secret").await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let response = rule_set("base", "shared-secret").await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_relayed_providers_keep_token_scheme() {
+        let (_file, store) = store_with(
+            r#"
+[proxies]
+a = "http://127.0.0.1:1/a"
+
+[server]
+public-url = "https://sub.example.com"
+
+[auth]
+enabled = true
+token = "shared-secret"
+"#,
+        );
+        let snapshot = store.snapshot();
+        let upstream = Arc::new(UpstreamCache::new());
+        let limiter = RateLimiter::new();
+        let subscribe = |params: HashMap<String, String>, headers: HeaderMap| {
+            let (snapshot, upstream, limiter) = (&snapshot, &upstream, &limiter);
+            async move {
+                let response = serve_profile(
+                    snapshot,
+                    upstream,
+                    limiter,
+                    None,
+                    Credentials::Token("shared-secret"),
+                    &params,
+                    &headers,
+                    false,
+                )
+                .await;
+                assert_eq!(response.status(), StatusCode::OK);
+                response.into_body()
+            }
+        };
+
+        // Bearer 客户端的 token 放在请求头里，不出现在链接中
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer shared-secret".parse().unwrap());
+        let body = subscribe(HashMap::new(), headers).await;
+        let config: serde_yaml::Value = serde_yaml::from_str(&body).unwrap();
+        let provider = &config["proxy-providers"]["a"];
+        assert_eq!(provider["url"], "https://sub.example.com/providers/a");
+        assert_eq!(
+            provider["header"]["Authorization"][0],
+            "Bearer shared-secret"
+        );
+
+        // 客户端自己把 token 放在查询参数中时沿用
+        let params = HashMap::from([("token".to_string(), "shared-secret".to_string())]);
+        let body = subscribe(params, HeaderMap::new()).await;
+        let config: serde_yaml::Value = serde_yaml::from_str(&body).unwrap();
+        let provider = &config["proxy-providers"]["a"];
+        assert_eq!(
+            provider["url"],
+            "https://sub.example.com/providers/a?token=shared-secret"
+        );
+        assert!(provider["header"].is_null());
+    }
+
+    #[test]
+    fn test_hash_token_warns_about_configured_token() {
+        let bob = hash_token("bob-secret", TokenHashScheme::Sha256);
+        let (_file, store) = store_with(&format!(
+            r#"
+[proxies]
+test = "https://example.com/clash"
+
+[auth]
+enabled = true
+
+[[auth.tokens]]
+label = "alice"
+token = "alice-secret"
+
+[[auth.tokens]]
+label = "bob"
+token = "{bob}"
+"#
+        ));
+        let app_config = store.current();
+
+        // 明文 token 正是要换成哈希的，只给出提示
+        let warning = token_reuse_warning(&app_config, "alice-secret").unwrap();
+        assert!(
+            warning.contains("'alice' is stored in plain text"),
+            "{warning}"
+        );
+        let warning = token_reuse_warning(&app_config, "bob-secret").unwrap();
+        assert!(warning.contains("already in use by 'bob'"), "{warning}");
+        assert!(token_reuse_warning(&app_config, "carol-secret").is_none());
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    sync::LazyLock,
+    time::{Duration, SystemTime},
+};
+
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+use crate::ConfigError;
+
+/// Prometheus 文本格式的 Content-Type
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+const NAMESPACE: &str = "sub_util";
+
+/// 服务运行指标
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    auth_failures: IntCounterVec,
+    rate_limited: IntCounterVec,
+    generation_errors: IntCounterVec,
+    config_reloads: IntCounterVec,
+    config_last_reload_success: Gauge,
+    upstream_fetches: IntCounterVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// 全局指标
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let opts = |name: &str, help: &str| Opts::new(name, help).namespace(NAMESPACE);
+
+        let http_requests = IntCounterVec::new(
+            opts("http_requests_total", "HTTP requests by route and status"),
+            &["route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route",
+            )
+            .namespace(NAMESPACE),
+            &["route"],
+        )
+        .unwrap();
+        let auth_failures = IntCounterVec::new(
+            opts("auth_failures_total", "Rejected requests by reason"),
+            &["reason"],
+        )
+        .unwrap();
+        let rate_limited = IntCounterVec::new(
+            opts("rate_limited_total", "Rate limited requests by reason"),
+            &["reason"],
+        )
+        .unwrap();
+        let generation_errors = IntCounterVec::new(
+            opts(
+                "config_generation_errors_total",
+                "Config generation errors by kind",
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        let config_reloads = IntCounterVec::new(
+            opts("config_reloads_total", "Config reloads by result"),
+            &["result"],
+        )
+        .unwrap();
+        let config_last_reload_success = Gauge::with_opts(opts(
+            "config_last_reload_success_timestamp_seconds",
+            "Unix time of the last successful config load",
+        ))
+        .unwrap();
+        let upstream_fetches = IntCounterVec::new(
+            opts(
+                "upstream_fetches_total",
+                "Upstream fetches by kind and outcome",
+            ),
+            &["kind", "outcome"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(auth_failures.clone())).unwrap();
+        registry.register(Box::new(rate_limited.clone())).unwrap();
+        registry
+            .register(Box::new(generation_errors.clone()))
+            .unwrap();
+        registry.register(Box::new(config_reloads.clone())).unwrap();
+        registry
+            .register(Box::new(config_last_reload_success.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_fetches.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            auth_failures,
+            rate_limited,
+            generation_errors,
+            config_reloads,
+            config_last_reload_success,
+            upstream_fetches,
+        }
+    }
+
+    /// 记录一次 HTTP 请求
+    pub fn observe_request(&self, route: &str, status: u16, elapsed: Duration) {
+        self.http_requests
+            .with_label_values(&[route, &status.to_string()])
+            .inc();
+        self.http_request_duration
+            .with_label_values(&[route])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// 记录一次认证失败
+    pub fn record_auth_failure(&self, reason: &str) {
+        self.auth_failures.with_label_values(&[reason]).inc();
+    }
+
+    /// 记录一次被限流的请求
+    pub fn record_rate_limited(&self, reason: &str) {
+        self.rate_limited.with_label_values(&[reason]).inc();
+    }
+
+    /// 记录一次配置生成错误
+    pub fn record_generation_error(&self, error: &ConfigError) {
+        self.generation_errors
+            .with_label_values(&[error.kind()])
+            .inc();
+    }
+
+    /// 记录一次配置重载
+    pub fn record_reload(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.config_reloads.with_label_values(&[result]).inc();
+        if success {
+            self.record_config_loaded();
+        }
+    }
+
+    /// 记录配置加载成功的时间
+    pub fn record_config_loaded(&self) {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.config_last_reload_success.set(now.as_secs_f64());
+    }
+
+    /// 记录一次上游请求，`kind` 为请求的内容类型
+    pub fn record_upstream_fetch(&self, kind: &str, success: bool) {
+        let outcome = if success { "success" } else { "error" };
+        self.upstream_fetches
+            .with_label_values(&[kind, outcome])
+            .inc();
+    }
+
+    /// 以 Prometheus 文本格式导出所有指标
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("failed to encode metrics");
+        String::from_utf8(buffer).expect("metrics are not valid UTF-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_metrics() {
+        let metrics = Metrics::new();
+        metrics.observe_request("/subs", 200, Duration::from_millis(5));
+        metrics.record_auth_failure("invalid_token");
+        metrics.record_rate_limited("locked_out");
+        metrics.record_generation_error(&ConfigError::RuleProcessingFailed("x".to_string()));
+        metrics.record_reload(true);
+        metrics.record_upstream_fetch("provider", false);
+
+        let output = metrics.encode();
+        assert!(output.contains(r#"sub_util_http_requests_total{route="/subs",status="200"} 1"#));
+        assert!(
+            output.contains(r#"sub_util_http_request_duration_seconds_count{route="/subs"} 1"#)
+        );
+        assert!(output.contains(r#"sub_util_auth_failures_total{reason="invalid_token"} 1"#));
+        assert!(output.contains(r#"sub_util_rate_limited_total{reason="locked_out"} 1"#));
+        assert!(output.contains(
+            r#"sub_util_config_generation_errors_total{kind="rule_processing_failed"} 1"#
+        ));
+        assert!(output.contains(r#"sub_util_config_reloads_total{result="success"} 1"#));
+        assert!(output.contains("sub_util_config_last_reload_success_timestamp_seconds"));
+        assert!(
+            output
+                .contains(r#"sub_util_upstream_fetches_total{kind="provider",outcome="error"} 1"#)
+        );
+    }
+}
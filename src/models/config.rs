@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize, Serializer, ser::SerializeSeq};
 
@@ -44,13 +44,13 @@ pub struct Config {
     pub proxies: Option<Vec<Proxy>>,
     /// proxy provider settings
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub proxy_providers: Option<HashMap<String, ProxyProvider>>,
+    pub proxy_providers: Option<BTreeMap<String, ProxyProvider>>,
     /// Proxy group settings
     #[serde(skip_serializing_if = "Option::is_none")]
     pub proxy_groups: Option<Vec<ProxyGroup>>,
     /// rule provider settings
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub rule_providers: Option<HashMap<String, RuleProvider>>,
+    pub rule_providers: Option<BTreeMap<String, RuleProvider>>,
     /// Rule settings
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rules: Option<Vec<Rule>>,
@@ -1,6 +1,7 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(tag = "type")]
 #[serde(rename_all = "kebab-case")]
 pub enum ProxyGroup {
@@ -11,14 +12,14 @@ pub enum ProxyGroup {
     Select(SelectGroup),
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct RelayGroup {
     #[serde(flatten)]
     pub common: ProxyGroupCommon,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub enum Strategy {
     RoundRobin,
@@ -26,7 +27,7 @@ pub enum Strategy {
     StickySession,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct LoadBalanceGroup {
     #[serde(flatten)]
@@ -35,14 +36,14 @@ pub struct LoadBalanceGroup {
     pub strategy: Option<Strategy>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct FallbackGroup {
     #[serde(flatten)]
     pub common: ProxyGroupCommon,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct UrlTestGroup {
     #[serde(flatten)]
@@ -51,14 +52,14 @@ pub struct UrlTestGroup {
     pub tolerance: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct SelectGroup {
     #[serde(flatten)]
     pub common: ProxyGroupCommon,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct ProxyGroupCommon {
     pub name: String,
@@ -1,5 +1,6 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
 
@@ -23,14 +24,14 @@ pub struct HealthCheck {
     pub lazy: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct ProxyName {
     pub pattern: String,
     pub target: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Override {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -44,7 +45,8 @@ pub struct Override {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub udp: Option<bool>,
     #[serde(flatten)]
-    pub extra: Option<HashMap<String, Value>>,
+    #[schemars(with = "Option<BTreeMap<String, serde_json::Value>>")]
+    pub extra: Option<BTreeMap<String, Value>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -75,7 +77,7 @@ pub struct HttpProxyProvider {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub size_limit: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub header: Option<HashMap<String, Vec<String>>>,
+    pub header: Option<BTreeMap<String, Vec<String>>>,
     #[serde(flatten)]
     pub common: ProxyProviderCommon,
 }
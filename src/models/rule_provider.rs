@@ -1,5 +1,6 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,7 +39,7 @@ pub struct HttpRuleProvider {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub proxy: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub header: Option<HashMap<String, Vec<String>>>,
+    pub header: Option<BTreeMap<String, Vec<String>>>,
     #[serde(flatten)]
     pub common: RuleProviderCommon,
 }
@@ -57,7 +58,7 @@ pub struct InlineRuleProvider {
     pub common: RuleProviderCommon,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum RuleSetBehavior {
     #[default]
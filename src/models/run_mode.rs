@@ -1,6 +1,7 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default, Copy, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum RunMode {
     Global,
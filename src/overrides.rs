@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{AppConfig, ConfigError, ProxyGroup, is_valid_url};
+
+const PROVIDER_PARAM_PREFIX: &str = "provider.";
+const ONLY_PARAM: &str = "only";
+
+/// 单次请求对订阅源的覆盖
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SubscriptionOverrides {
+    /// 替换指定订阅源的链接
+    #[serde(default)]
+    pub providers: HashMap<String, String>,
+    /// 仅保留指定的订阅源
+    #[serde(default)]
+    pub only: Option<Vec<String>>,
+}
+
+impl SubscriptionOverrides {
+    /// 从查询参数中解析覆盖项
+    ///
+    /// 支持 `provider.<name>=<url>` 和 `only=<name>,<name>`，其他参数会被忽略。
+    pub fn from_query(query: &HashMap<String, String>) -> Self {
+        let mut overrides = Self::default();
+        for (key, value) in query {
+            if let Some(name) = key.strip_prefix(PROVIDER_PARAM_PREFIX) {
+                overrides.providers.insert(name.to_string(), value.clone());
+            } else if key == ONLY_PARAM {
+                overrides.only = Some(
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                );
+            }
+        }
+        overrides
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty() && self.only.is_none()
+    }
+
+    /// 将覆盖项应用到配置上
+    pub fn apply(&self, app_config: &mut AppConfig) -> Result<(), ConfigError> {
+        for (name, url) in &self.providers {
+            let Some(current) = app_config.proxies.get_mut(name) else {
+                return Err(ConfigError::ConfigValidationFailed(format!(
+                    "Cannot override unknown provider: {name}"
+                )));
+            };
+            if !is_valid_url(url) {
+                return Err(ConfigError::InvalidSubscriptionUrl(format!(
+                    "{name}: {url}"
+                )));
+            }
+            current.set_url(url.clone());
+        }
+
+        if let Some(only) = &self.only {
+            if only.is_empty() {
+                return Err(ConfigError::ConfigValidationFailed(
+                    "At least one provider must be selected".to_string(),
+                ));
+            }
+            if let Some(unknown) = only
+                .iter()
+                .find(|name| !app_config.proxies.contains_key(*name))
+            {
+                return Err(ConfigError::ConfigValidationFailed(format!(
+                    "Cannot select unknown provider: {unknown}"
+                )));
+            }
+            app_config.proxies.retain(|name, _| only.contains(name));
+
+            // 移除代理组中对已排除订阅源的引用
+            for group in &mut app_config.groups {
+                let common = match group {
+                    ProxyGroup::Select(g) => &mut g.common,
+                    ProxyGroup::UrlTest(g) => &mut g.common,
+                    ProxyGroup::Fallback(g) => &mut g.common,
+                    ProxyGroup::LoadBalance(g) => &mut g.common,
+                    ProxyGroup::Relay(g) => &mut g.common,
+                };
+                let Some(use_provider) = &mut common.use_provider else {
+                    continue;
+                };
+                use_provider.retain(|name| only.contains(name));
+                if !use_provider.is_empty() {
+                    continue;
+                }
+                // 客户端不接受空的 `use`，没有其他节点时代理组无法使用
+                if common.proxies.as_ref().is_none_or(Vec::is_empty) {
+                    return Err(ConfigError::ConfigValidationFailed(format!(
+                        "Proxy group '{}' has no providers left after selecting: {}",
+                        common.name,
+                        only.join(",")
+                    )));
+                }
+                common.use_provider = None;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProxyGroupCommon, UrlTestGroup};
+
+    fn create_test_app_config() -> AppConfig {
+        let mut proxies = HashMap::new();
+        proxies.insert("a".to_string(), "https://a.example.com/clash".into());
+        proxies.insert("b".to_string(), "https://b.example.com/clash".into());
+
+        AppConfig {
+            proxies,
+            groups: vec![ProxyGroup::UrlTest(UrlTestGroup {
+                common: ProxyGroupCommon {
+                    name: "Auto".to_string(),
+                    proxies: None,
+                    use_provider: Some(vec!["a".to_string(), "b".to_string()]),
+                    url: Some("http://www.gstatic.com/generate_204".to_string()),
+                    interval: Some(300),
+                    lazy: None,
+                    timeout: None,
+                    max_failed_times: None,
+                    disable_udp: None,
+                    icon: None,
+                    filter: None,
+                },
+                tolerance: None,
+            })],
+            ..Default::default()
+        }
+    }
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_query() {
+        let overrides = SubscriptionOverrides::from_query(&query(&[
+            ("token", "secret"),
+            ("provider.a", "https://other.example.com/clash"),
+            ("only", "a, b,"),
+        ]));
+
+        assert_eq!(overrides.providers.len(), 1);
+        assert_eq!(
+            overrides.providers.get("a"),
+            Some(&"https://other.example.com/clash".to_string())
+        );
+        assert_eq!(overrides.only, Some(vec!["a".to_string(), "b".to_string()]));
+        assert!(SubscriptionOverrides::from_query(&query(&[("token", "x")])).is_empty());
+    }
+
+    #[test]
+    fn test_apply_replaces_provider_url() {
+        let mut app_config = create_test_app_config();
+        let overrides =
+            SubscriptionOverrides::from_query(&query(&[("provider.a", "https://new.example.com")]));
+
+        overrides.apply(&mut app_config).unwrap();
+        assert_eq!(app_config.proxies["a"].url(), "https://new.example.com");
+    }
+
+    #[test]
+    fn test_apply_rejects_invalid_overrides() {
+        let overrides = SubscriptionOverrides::from_query(&query(&[("provider.a", "not-a-url")]));
+        match overrides.apply(&mut create_test_app_config()) {
+            Err(ConfigError::InvalidSubscriptionUrl(_)) => {}
+            other => panic!("Expected InvalidSubscriptionUrl error, got {other:?}"),
+        }
+
+        let overrides =
+            SubscriptionOverrides::from_query(&query(&[("provider.c", "https://c.example.com")]));
+        assert!(overrides.apply(&mut create_test_app_config()).is_err());
+
+        let overrides = SubscriptionOverrides::from_query(&query(&[("only", "c")]));
+        assert!(overrides.apply(&mut create_test_app_config()).is_err());
+
+        let overrides = SubscriptionOverrides::from_query(&query(&[("only", "")]));
+        assert!(overrides.apply(&mut create_test_app_config()).is_err());
+    }
+
+    #[test]
+    fn test_apply_only_restricts_providers() {
+        let mut app_config = create_test_app_config();
+        let overrides = SubscriptionOverrides::from_query(&query(&[("only", "b")]));
+
+        overrides.apply(&mut app_config).unwrap();
+        assert_eq!(app_config.proxies.len(), 1);
+        assert!(app_config.proxies.contains_key("b"));
+
+        match &app_config.groups[0] {
+            ProxyGroup::UrlTest(url_test) => {
+                assert_eq!(url_test.common.use_provider, Some(vec!["b".to_string()]));
+            }
+            _ => panic!("Expected UrlTest group"),
+        }
+    }
+
+    #[test]
+    fn test_apply_only_drops_emptied_use() {
+        let mut app_config = create_test_app_config();
+        if let ProxyGroup::UrlTest(url_test) = &mut app_config.groups[0] {
+            url_test.common.use_provider = Some(vec!["a".to_string()]);
+            url_test.common.proxies = Some(vec!["DIRECT".to_string()]);
+        }
+        let overrides = SubscriptionOverrides::from_query(&query(&[("only", "b")]));
+
+        // 还有其他节点时去掉空的 `use`
+        overrides.apply(&mut app_config).unwrap();
+        match &app_config.groups[0] {
+            ProxyGroup::UrlTest(url_test) => assert_eq!(url_test.common.use_provider, None),
+            _ => panic!("Expected UrlTest group"),
+        }
+
+        // 没有其他节点时报告代理组
+        let mut app_config = create_test_app_config();
+        if let ProxyGroup::UrlTest(url_test) = &mut app_config.groups[0] {
+            url_test.common.use_provider = Some(vec!["a".to_string()]);
+        }
+        match overrides.apply(&mut app_config) {
+            Err(ConfigError::ConfigValidationFailed(msg)) => {
+                assert!(msg.contains("'Auto'"), "{msg}");
+            }
+            other => panic!("Expected ConfigValidationFailed error, got {other:?}"),
+        }
+    }
+}
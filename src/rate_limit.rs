@@ -0,0 +1,411 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::RateLimitConfig;
+
+/// 计数窗口，限流额度按分钟计算
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// 限流拒绝原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitError {
+    /// 请求过于频繁
+    TooManyRequests { retry_after: Duration },
+    /// 认证失败次数过多，IP 已被临时锁定
+    LockedOut { retry_after: Duration },
+}
+
+impl RateLimitError {
+    /// 建议客户端等待的时长
+    pub fn retry_after(&self) -> Duration {
+        match self {
+            RateLimitError::TooManyRequests { retry_after }
+            | RateLimitError::LockedOut { retry_after } => *retry_after,
+        }
+    }
+
+    /// 建议等待的秒数，向上取整，避免客户端过早重试
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after().as_secs_f64().ceil().max(1.0) as u64
+    }
+
+    /// 用于指标标签的拒绝原因
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RateLimitError::TooManyRequests { .. } => "too_many_requests",
+            RateLimitError::LockedOut { .. } => "locked_out",
+        }
+    }
+}
+
+impl fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.retry_after_secs();
+        match self {
+            RateLimitError::TooManyRequests { .. } => {
+                write!(f, "Too many requests, retry in {} seconds", seconds)
+            }
+            RateLimitError::LockedOut { .. } => {
+                write!(f, "Too many failed attempts, retry in {} seconds", seconds)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RateLimitError {}
+
+/// 令牌桶，容量为每分钟额度，按时间匀速回填
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: u32, now: Instant) -> Self {
+        Self {
+            tokens: limit as f64,
+            updated: now,
+        }
+    }
+
+    fn take(&mut self, limit: u32, now: Instant) -> Result<(), Duration> {
+        let rate = limit as f64 / WINDOW.as_secs_f64();
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(limit as f64);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+
+    /// 桶是否已回填满，满桶与新建的桶等价，可以丢弃
+    fn is_idle(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.updated) >= WINDOW
+    }
+}
+
+/// 限流和锁定所针对的客户端
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientAddr {
+    Ip(IpAddr),
+    /// 通过 unix socket 连接的客户端没有地址，共用一份额度
+    Unix,
+}
+
+impl fmt::Display for ClientAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientAddr::Ip(ip) => write!(f, "{ip}"),
+            ClientAddr::Unix => write!(f, "unix socket clients"),
+        }
+    }
+}
+
+/// 单个客户端的状态
+#[derive(Debug, Default)]
+struct IpState {
+    bucket: Option<Bucket>,
+    failures: u32,
+    last_failure: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+/// 按 IP 和 token 限流，并在多次认证失败后临时锁定 IP
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    ips: Mutex<HashMap<ClientAddr, IpState>>,
+    tokens: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 检查一次请求是否放行
+    ///
+    /// `credential` 为已通过认证的凭据标识，认证失败的请求只按 IP 限流和锁定，
+    /// 避免随意猜测的 token 占用令牌桶。
+    pub fn check(
+        &self,
+        config: &RateLimitConfig,
+        ip: Option<ClientAddr>,
+        credential: Option<&str>,
+    ) -> Result<(), RateLimitError> {
+        self.check_at(config, ip, credential, Instant::now())
+    }
+
+    /// 记录一次认证失败，达到上限后锁定该 IP
+    ///
+    /// 失败计数只随时间过期，认证成功不会清除，否则穿插使用一个有效凭据即可无限猜测其他 token。
+    pub fn record_failure(&self, config: &RateLimitConfig, ip: ClientAddr) {
+        self.record_failure_at(config, ip, Instant::now());
+    }
+
+    /// 清理过期的状态，避免内存无限增长
+    pub fn prune(&self, config: &RateLimitConfig) {
+        self.prune_at(config, Instant::now());
+    }
+
+    fn check_at(
+        &self,
+        config: &RateLimitConfig,
+        ip: Option<ClientAddr>,
+        credential: Option<&str>,
+        now: Instant,
+    ) -> Result<(), RateLimitError> {
+        if let Some(ip) = ip {
+            let mut ips = self.ips.lock().unwrap();
+            let state = ips.entry(ip).or_default();
+
+            if let Some(until) = state.locked_until {
+                if until > now {
+                    return Err(RateLimitError::LockedOut {
+                        retry_after: until - now,
+                    });
+                }
+                state.locked_until = None;
+                state.failures = 0;
+            }
+
+            if let Some(limit) = config.per_ip.filter(|limit| *limit > 0) {
+                state
+                    .bucket
+                    .get_or_insert_with(|| Bucket::full(limit, now))
+                    .take(limit, now)
+                    .map_err(|retry_after| RateLimitError::TooManyRequests { retry_after })?;
+            }
+        }
+
+        if let (Some(credential), Some(limit)) =
+            (credential, config.per_token.filter(|limit| *limit > 0))
+        {
+            self.tokens
+                .lock()
+                .unwrap()
+                .entry(credential.to_string())
+                .or_insert_with(|| Bucket::full(limit, now))
+                .take(limit, now)
+                .map_err(|retry_after| RateLimitError::TooManyRequests { retry_after })?;
+        }
+
+        Ok(())
+    }
+
+    fn record_failure_at(&self, config: &RateLimitConfig, ip: ClientAddr, now: Instant) {
+        let Some(max_failures) = config.max_failed_attempts.filter(|max| *max > 0) else {
+            return;
+        };
+        let lockout = Duration::from_secs(config.lockout_seconds);
+
+        let mut ips = self.ips.lock().unwrap();
+        let state = ips.entry(ip).or_default();
+        // 距上次失败超过锁定时长则重新计数
+        if state
+            .last_failure
+            .is_some_and(|last| now.saturating_duration_since(last) >= lockout)
+        {
+            state.failures = 0;
+        }
+        state.failures += 1;
+        state.last_failure = Some(now);
+
+        if state.failures >= max_failures {
+            tracing::warn!(
+                "Locking out {} for {}s after {} failed attempts",
+                ip,
+                lockout.as_secs(),
+                state.failures
+            );
+            state.locked_until = Some(now + lockout);
+            state.failures = 0;
+        }
+    }
+
+    fn prune_at(&self, config: &RateLimitConfig, now: Instant) {
+        let lockout = Duration::from_secs(config.lockout_seconds);
+        self.ips.lock().unwrap().retain(|_, state| {
+            state.locked_until.is_some_and(|until| until > now)
+                || state
+                    .last_failure
+                    .is_some_and(|last| now.saturating_duration_since(last) < lockout)
+                || state.bucket.is_some_and(|bucket| !bucket.is_idle(now))
+        });
+        self.tokens
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| !bucket.is_idle(now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(
+        per_ip: Option<u32>,
+        per_token: Option<u32>,
+        max_failed: Option<u32>,
+    ) -> RateLimitConfig {
+        RateLimitConfig {
+            per_ip,
+            per_token,
+            max_failed_attempts: max_failed,
+            lockout_seconds: 60,
+        }
+    }
+
+    fn ip(last: u8) -> Option<ClientAddr> {
+        Some(ClientAddr::Ip(IpAddr::from([192, 0, 2, last])))
+    }
+
+    #[test]
+    fn test_per_ip_limit() {
+        let limiter = RateLimiter::new();
+        let config = config(Some(2), None, None);
+        let now = Instant::now();
+
+        assert!(limiter.check_at(&config, ip(1), None, now).is_ok());
+        assert!(limiter.check_at(&config, ip(1), None, now).is_ok());
+        let err = limiter.check_at(&config, ip(1), None, now).unwrap_err();
+        assert_eq!(err.reason(), "too_many_requests");
+        assert_eq!(err.retry_after(), Duration::from_secs(30));
+        assert_eq!(err.retry_after_secs(), 30);
+
+        // 其他 IP 不受影响
+        assert!(limiter.check_at(&config, ip(2), None, now).is_ok());
+        // 额度随时间回填
+        let later = now + Duration::from_secs(30);
+        assert!(limiter.check_at(&config, ip(1), None, later).is_ok());
+    }
+
+    #[test]
+    fn test_per_token_limit() {
+        let limiter = RateLimiter::new();
+        let config = config(None, Some(1), None);
+        let now = Instant::now();
+
+        assert!(limiter.check_at(&config, ip(1), Some("a"), now).is_ok());
+        // 同一 token 换 IP 仍然受限
+        assert!(limiter.check_at(&config, ip(2), Some("a"), now).is_err());
+        assert!(limiter.check_at(&config, ip(2), Some("b"), now).is_ok());
+        // 未携带凭据的请求不计入 token 限流
+        assert!(limiter.check_at(&config, ip(2), None, now).is_ok());
+    }
+
+    #[test]
+    fn test_lockout_after_failures() {
+        let limiter = RateLimiter::new();
+        let config = config(None, None, Some(3));
+        let now = Instant::now();
+        let addr = ip(1).unwrap();
+
+        limiter.record_failure_at(&config, addr, now);
+        limiter.record_failure_at(&config, addr, now);
+        assert!(limiter.check_at(&config, ip(1), None, now).is_ok());
+
+        limiter.record_failure_at(&config, addr, now);
+        let err = limiter.check_at(&config, ip(1), None, now).unwrap_err();
+        assert_eq!(
+            err,
+            RateLimitError::LockedOut {
+                retry_after: Duration::from_secs(60)
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Too many failed attempts, retry in 60 seconds"
+        );
+
+        // 锁定到期后恢复
+        let later = now + Duration::from_secs(60);
+        assert!(limiter.check_at(&config, ip(1), None, later).is_ok());
+    }
+
+    #[test]
+    fn test_success_keeps_failures() {
+        let limiter = RateLimiter::new();
+        let config = config(None, None, Some(3));
+        let now = Instant::now();
+        let addr = ip(1).unwrap();
+
+        // 失败之间穿插成功的请求，仍然会被锁定
+        for _ in 0..2 {
+            limiter.record_failure_at(&config, addr, now);
+            assert!(limiter.check_at(&config, ip(1), Some("guest"), now).is_ok());
+        }
+        limiter.record_failure_at(&config, addr, now);
+        let err = limiter
+            .check_at(&config, ip(1), Some("guest"), now)
+            .unwrap_err();
+        assert_eq!(err.reason(), "locked_out");
+
+        // 失败计数在锁定时长后过期
+        let later = now + Duration::from_secs(60);
+        assert!(limiter.check_at(&config, ip(1), None, later).is_ok());
+        limiter.record_failure_at(&config, addr, later);
+        limiter.record_failure_at(&config, addr, later + Duration::from_secs(60));
+        assert!(
+            limiter
+                .check_at(&config, ip(1), None, later + Duration::from_secs(60))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_unix_clients_share_bucket() {
+        let limiter = RateLimiter::new();
+        let config = config(Some(1), None, Some(1));
+        let now = Instant::now();
+
+        assert!(
+            limiter
+                .check_at(&config, Some(ClientAddr::Unix), None, now)
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .check_at(&config, Some(ClientAddr::Unix), None, now)
+                .is_err()
+        );
+
+        let later = now + WINDOW;
+        limiter.record_failure_at(&config, ClientAddr::Unix, later);
+        let err = limiter
+            .check_at(&config, Some(ClientAddr::Unix), None, later)
+            .unwrap_err();
+        assert_eq!(err.reason(), "locked_out");
+        assert!(limiter.check_at(&config, ip(1), None, later).is_ok());
+    }
+
+    #[test]
+    fn test_prune() {
+        let limiter = RateLimiter::new();
+        let config = config(Some(10), Some(10), Some(5));
+        let now = Instant::now();
+
+        limiter.check_at(&config, ip(1), Some("a"), now).unwrap();
+        limiter.record_failure_at(&config, ip(2).unwrap(), now);
+
+        limiter.prune_at(&config, now);
+        assert_eq!(limiter.ips.lock().unwrap().len(), 2);
+        assert_eq!(limiter.tokens.lock().unwrap().len(), 1);
+
+        // 令牌桶回填满后即可丢弃，失败计数保留到锁定时长结束
+        limiter.prune_at(&config, now + Duration::from_secs(59));
+        assert_eq!(limiter.ips.lock().unwrap().len(), 2);
+        limiter.prune_at(&config, now + WINDOW);
+        assert!(limiter.ips.lock().unwrap().is_empty());
+        assert!(limiter.tokens.lock().unwrap().is_empty());
+    }
+}
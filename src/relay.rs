@@ -0,0 +1,217 @@
+use std::collections::{BTreeMap, HashSet};
+
+use reqwest::Url;
+
+use crate::{Config, ProxyProvider, RuleProvider};
+
+/// 拼接本服务对外的访问地址
+///
+/// `segments` 会按路径段追加到 `base_url` 之后并自动转义。
+pub fn build_public_url(
+    base_url: &str,
+    segments: &[&str],
+    query: &[(&str, &str)],
+) -> Option<String> {
+    let mut url = Url::parse(base_url).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    url.path_segments_mut()
+        .ok()?
+        .pop_if_empty()
+        .extend(segments);
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
+    Some(url.to_string())
+}
+
+/// 本服务的中转地址，以及客户端访问时需要附加的请求头
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelayTarget {
+    pub url: String,
+    pub header: BTreeMap<String, Vec<String>>,
+}
+
+impl From<String> for RelayTarget {
+    fn from(url: String) -> Self {
+        Self {
+            url,
+            header: BTreeMap::new(),
+        }
+    }
+}
+
+/// 替换链接，并把请求头合并到订阅已有的 `header` 中
+fn apply_target(
+    url: &mut String,
+    header: &mut Option<BTreeMap<String, Vec<String>>>,
+    target: RelayTarget,
+) {
+    *url = target.url;
+    if !target.header.is_empty() {
+        header.get_or_insert_default().extend(target.header);
+    }
+}
+
+/// 将生成配置中的订阅链接替换为本服务的中转地址
+///
+/// `skip` 中的订阅保留原链接；`relay_url` 根据订阅名称返回中转地址。
+pub fn relay_proxy_providers(
+    config: &mut Config,
+    skip: &HashSet<String>,
+    mut relay_url: impl FnMut(&str) -> Option<RelayTarget>,
+) {
+    let Some(providers) = &mut config.proxy_providers else {
+        return;
+    };
+    for (name, provider) in providers.iter_mut() {
+        if skip.contains(name) {
+            continue;
+        }
+        if let ProxyProvider::Http(http_provider) = provider
+            && let Some(target) = relay_url(name)
+        {
+            apply_target(&mut http_provider.url, &mut http_provider.header, target);
+        }
+    }
+}
+
+/// 将生成配置中的规则集链接替换为本服务的镜像地址
+///
+/// `mirror_url` 根据规则集名称和原链接返回镜像地址，返回 `None` 时保留原链接。
+pub fn relay_rule_providers(
+    config: &mut Config,
+    mut mirror_url: impl FnMut(&str, &str) -> Option<RelayTarget>,
+) {
+    let Some(providers) = &mut config.rule_providers else {
+        return;
+    };
+    for (name, provider) in providers.iter_mut() {
+        if let RuleProvider::Http(http_provider) = provider
+            && let Some(target) = mirror_url(name, &http_provider.url)
+        {
+            apply_target(&mut http_provider.url, &mut http_provider.header, target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AppConfig, RuleCfg, RuleSetBehavior, RuleSetCfg, generate_clash_config};
+
+    #[test]
+    fn test_build_public_url() {
+        assert_eq!(
+            build_public_url("https://sub.example.com", &["providers", "a"], &[]).unwrap(),
+            "https://sub.example.com/providers/a"
+        );
+        assert_eq!(
+            build_public_url(
+                "http://127.0.0.1:3000/base/",
+                &["providers", "my provider"],
+                &[("token", "a&b")]
+            )
+            .unwrap(),
+            "http://127.0.0.1:3000/base/providers/my%20provider?token=a%26b"
+        );
+        assert!(build_public_url("not a url", &["providers"], &[]).is_none());
+        assert!(build_public_url("ftp://example.com", &["providers"], &[]).is_none());
+    }
+
+    #[test]
+    fn test_relay_proxy_providers() {
+        let mut app_config = AppConfig::default();
+        app_config.proxies.insert(
+            "a".to_string(),
+            "https://a.example.com/sub?token=secret".into(),
+        );
+        app_config
+            .proxies
+            .insert("b".to_string(), "https://b.example.com/sub".into());
+        let mut config = generate_clash_config(app_config);
+
+        let skip = HashSet::from(["b".to_string()]);
+        relay_proxy_providers(&mut config, &skip, |name| {
+            let url = build_public_url("https://sub.example.com", &["providers", name], &[])?;
+            Some(RelayTarget {
+                url,
+                header: BTreeMap::from([(
+                    "Authorization".to_string(),
+                    vec!["Bearer t".to_string()],
+                )]),
+            })
+        });
+
+        let providers = config.proxy_providers.unwrap();
+        match providers.get("a").unwrap() {
+            ProxyProvider::Http(http_provider) => {
+                assert_eq!(http_provider.url, "https://sub.example.com/providers/a");
+                let header = http_provider.header.as_ref().unwrap();
+                assert_eq!(header["Authorization"], vec!["Bearer t".to_string()]);
+            }
+            _ => panic!("Expected HTTP provider"),
+        }
+        match providers.get("b").unwrap() {
+            ProxyProvider::Http(http_provider) => {
+                assert_eq!(http_provider.url, "https://b.example.com/sub");
+                assert!(http_provider.header.is_none());
+            }
+            _ => panic!("Expected HTTP provider"),
+        }
+    }
+
+    fn app_config_rules() -> Vec<RuleCfg> {
+        vec![RuleCfg::Set(RuleSetCfg {
+            name: "ad".to_string(),
+            url: "https://cdn.example.com/ad.txt".to_string(),
+            behavior: RuleSetBehavior::Domain,
+            target: "REJECT".to_string(),
+            interval: None,
+        })]
+    }
+
+    #[test]
+    fn test_relay_rule_providers() {
+        let app_config = AppConfig {
+            rules: app_config_rules(),
+            ..Default::default()
+        };
+        let mut config = generate_clash_config(app_config);
+
+        relay_rule_providers(&mut config, |name, url| {
+            assert_eq!(url, "https://cdn.example.com/ad.txt");
+            build_public_url(
+                "https://sub.example.com",
+                &["rules", name],
+                &[("token", "t")],
+            )
+            .map(RelayTarget::from)
+        });
+
+        match config.rule_providers.as_ref().unwrap().get("ad").unwrap() {
+            RuleProvider::Http(http_provider) => {
+                assert_eq!(
+                    http_provider.url,
+                    "https://sub.example.com/rules/ad?token=t"
+                );
+                assert!(http_provider.header.is_none());
+            }
+            _ => panic!("Expected HTTP rule provider"),
+        }
+
+        // 返回 None 时保留原链接
+        let mut config = generate_clash_config(AppConfig {
+            rules: app_config_rules(),
+            ..Default::default()
+        });
+        relay_rule_providers(&mut config, |_, _| None);
+        match config.rule_providers.unwrap().get("ad").unwrap() {
+            RuleProvider::Http(http_provider) => {
+                assert_eq!(http_provider.url, "https://cdn.example.com/ad.txt");
+            }
+            _ => panic!("Expected HTTP rule provider"),
+        }
+    }
+}
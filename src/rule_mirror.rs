@@ -0,0 +1,283 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+
+use crate::{
+    AppConfig, RuleCfg, RuleSetCfg, UpstreamError, content_hash, get_rule_set_update_interval,
+    metrics,
+    upstream::{http_client, read_body},
+};
+
+/// 规则集大小上限，避免异常的上游占满内存和磁盘
+const RULE_SET_SIZE_LIMIT: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum MirrorError {
+    Io(io::Error),
+    Upstream(UpstreamError),
+}
+
+impl fmt::Display for MirrorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MirrorError::Io(err) => write!(f, "io error: {err}"),
+            MirrorError::Upstream(err) => write!(f, "upstream error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for MirrorError {}
+
+impl From<io::Error> for MirrorError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<UpstreamError> for MirrorError {
+    fn from(value: UpstreamError) -> Self {
+        Self::Upstream(value)
+    }
+}
+
+/// 镜像在本地的规则集内容
+#[derive(Debug, Clone)]
+pub struct MirroredRuleSet {
+    pub body: Bytes,
+    pub modified: SystemTime,
+}
+
+/// 规则集镜像，将上游规则集缓存在磁盘上
+pub struct RuleMirror {
+    client: reqwest::Client,
+}
+
+impl Default for RuleMirror {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RuleMirror {
+    pub fn new() -> Self {
+        Self {
+            client: http_client(),
+        }
+    }
+
+    /// 规则集在缓存目录中的文件路径
+    ///
+    /// 文件名包含链接的摘要，规则集链接变化后会重新下载。
+    pub fn cache_path(cache_dir: &Path, rule_set: &RuleSetCfg) -> PathBuf {
+        let name: String = rule_set
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let hash = content_hash(&[rule_set.url.as_bytes()]);
+        cache_dir.join(format!("{name}-{}", &hash[..16]))
+    }
+
+    /// 读取规则集，本地没有缓存时从上游下载
+    pub async fn get(
+        &self,
+        cache_dir: &Path,
+        rule_set: &RuleSetCfg,
+    ) -> Result<MirroredRuleSet, MirrorError> {
+        let path = Self::cache_path(cache_dir, rule_set);
+        match read_cached(&path).await {
+            Ok(cached) => Ok(cached),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.refresh(cache_dir, rule_set).await
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// 从上游下载规则集并写入缓存
+    pub async fn refresh(
+        &self,
+        cache_dir: &Path,
+        rule_set: &RuleSetCfg,
+    ) -> Result<MirroredRuleSet, MirrorError> {
+        let fetched = self.fetch(&rule_set.url).await;
+        metrics().record_upstream_fetch("rule_set", fetched.is_ok());
+        let body = fetched?;
+
+        // 先写入唯一命名的临时文件再重命名，避免读取到写了一半的文件，
+        // 同时刷新同一规则集时也不会互相覆盖临时文件
+        tokio::fs::create_dir_all(cache_dir).await?;
+        let path = Self::cache_path(cache_dir, rule_set);
+        let dir = cache_dir.to_path_buf();
+        let contents = body.clone();
+        tokio::task::spawn_blocking(move || -> io::Result<()> {
+            let mut file = tempfile::NamedTempFile::new_in(dir)?;
+            file.write_all(&contents)?;
+            file.persist(&path)?;
+            Ok(())
+        })
+        .await
+        .map_err(io::Error::other)??;
+
+        Ok(MirroredRuleSet {
+            body,
+            modified: SystemTime::now(),
+        })
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Bytes, UpstreamError> {
+        let response = self.client.get(url).send().await?;
+        if !response.status().is_success() {
+            return Err(UpstreamError::Status(response.status().as_u16()));
+        }
+        read_body(response, Some(RULE_SET_SIZE_LIMIT)).await
+    }
+
+    /// 刷新所有已超过更新间隔的规则集
+    ///
+    /// 返回刷新失败的规则集名称及错误。
+    pub async fn refresh_due(
+        &self,
+        cache_dir: &Path,
+        rule_sets: &[RuleSetCfg],
+    ) -> Vec<(String, MirrorError)> {
+        let mut errors = Vec::new();
+        for rule_set in rule_sets {
+            let path = Self::cache_path(cache_dir, rule_set);
+            let interval = Duration::from_secs(get_rule_set_update_interval(rule_set));
+            let age = tokio::fs::metadata(&path)
+                .await
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok());
+            if age.is_some_and(|age| age < interval) {
+                continue;
+            }
+            if let Err(err) = self.refresh(cache_dir, rule_set).await {
+                errors.push((rule_set.name.clone(), err));
+            }
+        }
+        errors
+    }
+}
+
+/// 收集基础配置和所有配置档中的规则集，同名规则集以先出现的为准
+pub fn collect_rule_sets(app_config: &AppConfig) -> Vec<RuleSetCfg> {
+    let mut profiles: Vec<_> = app_config.profiles.iter().collect();
+    profiles.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut seen = HashSet::new();
+    std::iter::once(&app_config.rules)
+        .chain(profiles.into_iter().filter_map(|(_, p)| p.rules.as_ref()))
+        .flatten()
+        .filter_map(|rule| match rule {
+            RuleCfg::Set(rule_set) => Some(rule_set),
+            RuleCfg::Single(_) => None,
+        })
+        .filter(|rule_set| seen.insert(rule_set.name.clone()))
+        .cloned()
+        .collect()
+}
+
+async fn read_cached(path: &Path) -> io::Result<MirroredRuleSet> {
+    let body = tokio::fs::read(path).await?;
+    let modified = tokio::fs::metadata(path).await?.modified()?;
+    Ok(MirroredRuleSet {
+        body: body.into(),
+        modified,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProfileConfig, RuleSetBehavior};
+
+    fn rule_set(name: &str, url: &str) -> RuleSetCfg {
+        RuleSetCfg {
+            name: name.to_string(),
+            url: url.to_string(),
+            behavior: RuleSetBehavior::Domain,
+            target: "DIRECT".to_string(),
+            interval: None,
+        }
+    }
+
+    #[test]
+    fn test_cache_path() {
+        let dir = Path::new("/tmp/rules");
+        let path = RuleMirror::cache_path(dir, &rule_set("../evil name", "https://a.example.com"));
+        assert_eq!(path.parent(), Some(dir));
+        assert!(
+            path.file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .starts_with("___evil_name-")
+        );
+
+        // 链接变化后使用新的缓存文件
+        let other = RuleMirror::cache_path(dir, &rule_set("../evil name", "https://b.example.com"));
+        assert_ne!(path, other);
+    }
+
+    #[tokio::test]
+    async fn test_get_reads_cached_file() {
+        let dir = tempfile::tempdir().unwrap();
+        // 链接无法访问，只能读取磁盘缓存
+        let rule_set = rule_set("cached", "http://127.0.0.1:1/rules.yaml");
+        let path = RuleMirror::cache_path(dir.path(), &rule_set);
+        std::fs::write(&path, "payload:\n  - example.com\n").unwrap();
+
+        let mirror = RuleMirror::new();
+        let mirrored = mirror.get(dir.path(), &rule_set).await.unwrap();
+        assert_eq!(&mirrored.body[..], b"payload:\n  - example.com\n");
+
+        // 缓存未过期时不会刷新
+        let errors = mirror
+            .refresh_due(dir.path(), std::slice::from_ref(&rule_set))
+            .await;
+        assert!(errors.is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(mirror.get(dir.path(), &rule_set).await.is_err());
+    }
+
+    #[test]
+    fn test_collect_rule_sets() {
+        let mut app_config = AppConfig {
+            rules: vec![
+                RuleCfg::Set(rule_set("a", "https://example.com/a")),
+                RuleCfg::Set(rule_set("b", "https://example.com/b")),
+            ],
+            ..Default::default()
+        };
+        app_config.profiles.insert(
+            "phone".to_string(),
+            ProfileConfig {
+                rules: Some(vec![
+                    RuleCfg::Set(rule_set("a", "https://example.com/other")),
+                    RuleCfg::Set(rule_set("c", "https://example.com/c")),
+                ]),
+                ..Default::default()
+            },
+        );
+
+        let rule_sets = collect_rule_sets(&app_config);
+        let names: Vec<_> = rule_sets.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert_eq!(rule_sets[0].url, "https://example.com/a");
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use tokio_rustls::rustls::{
+    self, ServerConfig,
+    crypto::{CryptoProvider, ring},
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+
+use crate::config_store::file_modified;
+
+#[derive(Debug)]
+pub enum TlsError {
+    /// 证书或私钥文件无法读取或解析
+    Pem {
+        path: PathBuf,
+        error: rustls::pki_types::pem::Error,
+    },
+    /// 证书文件中没有证书
+    NoCertificates(PathBuf),
+    /// 私钥不受支持或与证书不匹配
+    InvalidKey(rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Pem { path, error } => {
+                write!(f, "failed to read {}: {error}", path.display())
+            }
+            TlsError::NoCertificates(path) => {
+                write!(f, "no certificates found in {}", path.display())
+            }
+            TlsError::InvalidKey(err) => write!(f, "invalid private key: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+/// 从 PEM 文件加载的证书，文件变化后可以重新加载而无需重启
+#[derive(Debug)]
+pub struct TlsCertificates {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<LoadedCertificate>,
+}
+
+#[derive(Debug, Clone)]
+struct LoadedCertificate {
+    key: Arc<CertifiedKey>,
+    modified: (Option<SystemTime>, Option<SystemTime>),
+}
+
+impl TlsCertificates {
+    pub fn load(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> Result<Self, TlsError> {
+        let cert_path = cert_path.as_ref().to_path_buf();
+        let key_path = key_path.as_ref().to_path_buf();
+        let provider = Arc::new(ring::default_provider());
+        let current = load_certificate(&provider, &cert_path, &key_path)?;
+        Ok(Self {
+            cert_path,
+            key_path,
+            provider,
+            current: RwLock::new(current),
+        })
+    }
+
+    /// 当证书或私钥文件的修改时间变化时重新加载
+    ///
+    /// 文件未变化时返回 `Ok(false)`，加载失败时继续使用之前的证书。
+    pub fn reload_if_changed(&self) -> Result<bool, TlsError> {
+        let modified = (
+            file_modified(&self.cert_path),
+            file_modified(&self.key_path),
+        );
+        if modified == self.current.read().unwrap().modified {
+            return Ok(false);
+        }
+
+        let result = load_certificate(&self.provider, &self.cert_path, &self.key_path);
+        let mut current = self.current.write().unwrap();
+        match result {
+            Ok(loaded) => {
+                *current = loaded;
+                Ok(true)
+            }
+            Err(err) => {
+                // 记录失败版本的修改时间，避免对同一组坏文件反复重试
+                current.modified = modified;
+                Err(err)
+            }
+        }
+    }
+
+    /// 构建使用这些证书的 rustls 服务端配置
+    pub fn server_config(self: &Arc<Self>) -> Arc<ServerConfig> {
+        let mut config = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default protocol versions")
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        // axum 只启用了 HTTP/1
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Arc::new(config)
+    }
+}
+
+impl ResolvesServerCert for TlsCertificates {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().key.clone())
+    }
+}
+
+fn load_certificate(
+    provider: &CryptoProvider,
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<LoadedCertificate, TlsError> {
+    // 先记录修改时间，读取期间文件被替换时下次检查会再次加载
+    let modified = (file_modified(cert_path), file_modified(key_path));
+    let pem_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |error| TlsError::Pem { path, error }
+    };
+
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .map_err(pem_error(cert_path))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(pem_error(cert_path))?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(cert_path.to_path_buf()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(pem_error(key_path))?;
+
+    let key = CertifiedKey::from_der(certs, key, provider).map_err(TlsError::InvalidKey)?;
+    Ok(LoadedCertificate {
+        key: Arc::new(key),
+        modified,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+    use tempfile::TempDir;
+
+    /// 生成自签名证书并写入目录，返回证书和私钥路径
+    fn write_certificate(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+        let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        fs::write(&cert_path, cert.cert.pem()).unwrap();
+        fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+        // 确保修改时间发生变化
+        let bumped = SystemTime::now() + Duration::from_secs(1);
+        for path in [&cert_path, &key_path] {
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(bumped)
+                .unwrap();
+        }
+        (cert_path, key_path)
+    }
+
+    fn current_cert(certificates: &TlsCertificates) -> CertificateDer<'static> {
+        certificates.current.read().unwrap().key.cert[0].clone()
+    }
+
+    #[test]
+    fn test_load_certificates() {
+        let dir = TempDir::new().unwrap();
+        let (cert_path, key_path) = write_certificate(dir.path(), "sub.example.com");
+
+        let certificates = Arc::new(TlsCertificates::load(&cert_path, &key_path).unwrap());
+        let config = certificates.server_config();
+        assert_eq!(config.alpn_protocols, vec![b"http/1.1".to_vec()]);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let dir = TempDir::new().unwrap();
+        let (cert_path, key_path) = write_certificate(dir.path(), "sub.example.com");
+        let missing = dir.path().join("missing.pem");
+
+        let err = TlsCertificates::load(&missing, &key_path).unwrap_err();
+        assert!(matches!(err, TlsError::Pem { ref path, .. } if *path == missing));
+        assert!(err.to_string().contains("missing.pem"));
+
+        // 私钥文件中没有证书
+        let err = TlsCertificates::load(&key_path, &key_path).unwrap_err();
+        assert!(matches!(err, TlsError::NoCertificates(_)));
+
+        // 私钥与证书不匹配
+        let other = TempDir::new().unwrap();
+        let (_, other_key) = write_certificate(other.path(), "other.example.com");
+        let err = TlsCertificates::load(&cert_path, &other_key).unwrap_err();
+        assert!(matches!(err, TlsError::InvalidKey(_)));
+    }
+
+    #[test]
+    fn test_reload_if_changed() {
+        let dir = TempDir::new().unwrap();
+        let (cert_path, key_path) = write_certificate(dir.path(), "old.example.com");
+        let certificates = TlsCertificates::load(&cert_path, &key_path).unwrap();
+        let old = current_cert(&certificates);
+
+        // 文件未变化时不重新加载
+        assert!(!certificates.reload_if_changed().unwrap());
+
+        write_certificate(dir.path(), "new.example.com");
+        assert!(certificates.reload_if_changed().unwrap());
+        assert_ne!(current_cert(&certificates), old);
+
+        // 加载失败时保留之前的证书
+        let renewed = current_cert(&certificates);
+        fs::write(&cert_path, "not a certificate").unwrap();
+        assert!(certificates.reload_if_changed().is_err());
+        assert_eq!(current_cert(&certificates), renewed);
+    }
+}
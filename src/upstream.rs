@@ -0,0 +1,714 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use bytes::{Bytes, BytesMut};
+use serde::Serialize;
+use tokio::{sync::OnceCell, task::JoinSet};
+
+use crate::{ProxySource, metrics};
+
+/// 上游订阅响应中的流量信息头
+pub const SUBSCRIPTION_USERINFO_HEADER: &str = "subscription-userinfo";
+
+const USER_AGENT: &str = concat!("clash.meta sub-util/", env!("CARGO_PKG_VERSION"));
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const USERINFO_CACHE_TTL: Duration = Duration::from_secs(600);
+const PROVIDER_CACHE_TTL: Duration = Duration::from_secs(300);
+/// 过期的缓存在上游失败或后台刷新期间仍会使用，超过该时长后才清理
+const STALE_TTL: Duration = Duration::from_secs(86400);
+
+/// 订阅流量信息
+///
+/// 格式为 `upload=<bytes>; download=<bytes>; total=<bytes>; expire=<unix 时间戳>`。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct SubscriptionUserinfo {
+    pub upload: u64,
+    pub download: u64,
+    pub total: u64,
+    /// 过期时间，`None` 表示不过期
+    pub expire: Option<u64>,
+}
+
+impl SubscriptionUserinfo {
+    /// 合并多个订阅的流量信息：流量求和，过期时间取最早的一个
+    pub fn aggregate<'a>(
+        infos: impl IntoIterator<Item = &'a SubscriptionUserinfo>,
+    ) -> Option<Self> {
+        infos.into_iter().fold(None, |acc, info| {
+            let Some(acc) = acc else {
+                return Some(*info);
+            };
+            Some(SubscriptionUserinfo {
+                upload: acc.upload.saturating_add(info.upload),
+                download: acc.download.saturating_add(info.download),
+                total: acc.total.saturating_add(info.total),
+                expire: match (acc.expire, info.expire) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                },
+            })
+        })
+    }
+}
+
+impl FromStr for SubscriptionUserinfo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut info = SubscriptionUserinfo::default();
+        for part in s.split(';') {
+            let part = part.trim();
+            if part.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = part.split_once('=') else {
+                return Err(format!("invalid subscription userinfo field: {part}"));
+            };
+            let value = value.trim();
+            // 部分服务商会返回浮点数
+            let parsed = value
+                .parse::<u64>()
+                .or_else(|_| value.parse::<f64>().map(|v| v.max(0.0) as u64))
+                .map_err(|_| format!("invalid subscription userinfo value: {part}"))?;
+            match key.trim() {
+                "upload" => info.upload = parsed,
+                "download" => info.download = parsed,
+                "total" => info.total = parsed,
+                // 0 表示不过期
+                "expire" => info.expire = (parsed > 0).then_some(parsed),
+                _ => {}
+            }
+        }
+        Ok(info)
+    }
+}
+
+impl fmt::Display for SubscriptionUserinfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "upload={}; download={}; total={}",
+            self.upload, self.download, self.total
+        )?;
+        if let Some(expire) = self.expire {
+            write!(f, "; expire={expire}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum UpstreamError {
+    Request(reqwest::Error),
+    Status(u16),
+    /// 订阅内容超过 `size-limit`
+    TooLarge(u64),
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamError::Request(err) => write!(f, "request error: {err}"),
+            UpstreamError::Status(status) => write!(f, "upstream returned status {status}"),
+            UpstreamError::TooLarge(limit) => {
+                write!(f, "upstream response exceeds size limit of {limit} bytes")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UpstreamError {}
+
+impl From<reqwest::Error> for UpstreamError {
+    fn from(value: reqwest::Error) -> Self {
+        // 订阅链接中通常带有凭据，不能出现在错误信息里
+        Self::Request(value.without_url())
+    }
+}
+
+/// 创建访问上游使用的 HTTP 客户端
+pub(crate) fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .expect("failed to build HTTP client")
+}
+
+/// 请求上游订阅的方式，链接相同但请求头或大小限制不同的订阅分别缓存
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct UpstreamRequest {
+    pub url: String,
+    /// 附加的请求头，设置 User-Agent 时替换默认值
+    pub headers: BTreeMap<String, Vec<String>>,
+    /// 订阅内容的大小上限（字节），0 表示不限制
+    pub size_limit: Option<u64>,
+}
+
+impl From<&ProxySource> for UpstreamRequest {
+    fn from(source: &ProxySource) -> Self {
+        let config = source.config();
+        Self {
+            url: source.url().to_string(),
+            headers: config
+                .and_then(|config| config.header.clone())
+                .unwrap_or_default(),
+            size_limit: config.and_then(|config| config.size_limit),
+        }
+    }
+}
+
+struct CachedUserinfo {
+    info: Option<SubscriptionUserinfo>,
+    fetched_at: Instant,
+}
+
+/// 上游订阅的内容
+#[derive(Debug, Clone)]
+pub struct ProviderContent {
+    pub body: Bytes,
+    pub content_type: Option<String>,
+    pub userinfo: Option<SubscriptionUserinfo>,
+    pub fetched_at: SystemTime,
+}
+
+struct CachedProvider {
+    content: ProviderContent,
+    cached_at: Instant,
+}
+
+/// 从上游订阅获取信息并缓存
+pub struct UpstreamCache {
+    client: reqwest::Client,
+    userinfo: Mutex<HashMap<UpstreamRequest, CachedUserinfo>>,
+    /// 正在获取的流量信息，同一订阅的并发请求共用一次获取
+    pending_userinfo: Mutex<HashMap<UpstreamRequest, Arc<OnceCell<Option<SubscriptionUserinfo>>>>>,
+    providers: Mutex<HashMap<UpstreamRequest, CachedProvider>>,
+}
+
+impl Default for UpstreamCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UpstreamCache {
+    pub fn new() -> Self {
+        Self {
+            client: http_client(),
+            userinfo: Mutex::new(HashMap::new()),
+            pending_userinfo: Mutex::new(HashMap::new()),
+            providers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 获取上游订阅的完整内容
+    ///
+    /// 内容会缓存一段时间；上游请求失败时，如果有旧的缓存则继续使用旧内容。
+    pub async fn provider(
+        &self,
+        request: &UpstreamRequest,
+    ) -> Result<ProviderContent, UpstreamError> {
+        let stale = {
+            let providers = self.providers.lock().unwrap();
+            match providers.get(request) {
+                Some(cached) if cached.cached_at.elapsed() < PROVIDER_CACHE_TTL => {
+                    return Ok(cached.content.clone());
+                }
+                Some(cached) => Some(cached.content.clone()),
+                None => None,
+            }
+        };
+
+        match self.fetch_provider(request).await {
+            Ok(content) => {
+                let now = Instant::now();
+                // 顺便刷新流量信息缓存
+                self.userinfo.lock().unwrap().insert(
+                    request.clone(),
+                    CachedUserinfo {
+                        info: content.userinfo,
+                        fetched_at: now,
+                    },
+                );
+                self.providers.lock().unwrap().insert(
+                    request.clone(),
+                    CachedProvider {
+                        content: content.clone(),
+                        cached_at: now,
+                    },
+                );
+                Ok(content)
+            }
+            Err(err) => match stale {
+                Some(content) => {
+                    tracing::warn!("Failed to refresh provider, serving cached copy: {}", err);
+                    Ok(content)
+                }
+                None => Err(err),
+            },
+        }
+    }
+
+    /// 获取单个订阅的流量信息
+    ///
+    /// 结果（包括获取失败）会缓存一段时间，避免频繁请求上游。缓存过期后先返回旧值，
+    /// 在后台刷新，只有从未获取过的订阅才需要等待上游。
+    pub async fn userinfo(
+        self: &Arc<Self>,
+        request: &UpstreamRequest,
+    ) -> Option<SubscriptionUserinfo> {
+        let cached = self
+            .userinfo
+            .lock()
+            .unwrap()
+            .get(request)
+            .map(|cached| (cached.info, cached.fetched_at.elapsed()));
+        match cached {
+            Some((info, age)) if age < USERINFO_CACHE_TTL => info,
+            Some((info, _)) => {
+                let cache = self.clone();
+                let request = request.clone();
+                tokio::spawn(async move { cache.refresh_userinfo(&request).await });
+                info
+            }
+            None => self.refresh_userinfo(request).await,
+        }
+    }
+
+    /// 从上游获取流量信息并写入缓存，同一订阅同时只有一个请求
+    async fn refresh_userinfo(&self, request: &UpstreamRequest) -> Option<SubscriptionUserinfo> {
+        let pending = self
+            .pending_userinfo
+            .lock()
+            .unwrap()
+            .entry(request.clone())
+            .or_default()
+            .clone();
+        let info = *pending
+            .get_or_init(|| async {
+                let info = match self.fetch_userinfo(request).await {
+                    Ok(info) => info,
+                    Err(err) => {
+                        tracing::warn!("Failed to fetch subscription userinfo: {}", err);
+                        None
+                    }
+                };
+                self.userinfo.lock().unwrap().insert(
+                    request.clone(),
+                    CachedUserinfo {
+                        info,
+                        fetched_at: Instant::now(),
+                    },
+                );
+                info
+            })
+            .await;
+
+        // 结果已写入缓存，之后的刷新重新请求上游
+        let mut pending_userinfo = self.pending_userinfo.lock().unwrap();
+        if pending_userinfo
+            .get(request)
+            .is_some_and(|current| Arc::ptr_eq(current, &pending))
+        {
+            pending_userinfo.remove(request);
+        }
+        info
+    }
+
+    /// 并发获取所有订阅的流量信息，按订阅名称返回
+    pub async fn collect_userinfo(
+        self: &Arc<Self>,
+        proxies: &HashMap<String, ProxySource>,
+    ) -> HashMap<String, Option<SubscriptionUserinfo>> {
+        let mut tasks = JoinSet::new();
+        for (name, source) in proxies {
+            let cache = self.clone();
+            let name = name.clone();
+            let request = UpstreamRequest::from(source);
+            tasks.spawn(async move { (name, cache.userinfo(&request).await) });
+        }
+
+        let mut result = HashMap::new();
+        while let Some(joined) = tasks.join_next().await {
+            if let Ok((name, info)) = joined {
+                result.insert(name, info);
+            }
+        }
+        result
+    }
+
+    /// 清理过期的缓存，避免不再使用的链接一直占用内存
+    pub fn prune(&self) {
+        self.prune_at(Instant::now());
+    }
+
+    fn prune_at(&self, now: Instant) {
+        self.userinfo
+            .lock()
+            .unwrap()
+            .retain(|_, cached| now.saturating_duration_since(cached.fetched_at) < STALE_TTL);
+        self.providers
+            .lock()
+            .unwrap()
+            .retain(|_, cached| now.saturating_duration_since(cached.cached_at) < STALE_TTL);
+    }
+
+    async fn fetch_userinfo(
+        &self,
+        request: &UpstreamRequest,
+    ) -> Result<Option<SubscriptionUserinfo>, UpstreamError> {
+        let result = self.send(request).await;
+        metrics().record_upstream_fetch("userinfo", result.is_ok());
+        Ok(parse_userinfo(&result?))
+    }
+
+    async fn fetch_provider(
+        &self,
+        request: &UpstreamRequest,
+    ) -> Result<ProviderContent, UpstreamError> {
+        let result = self.fetch_provider_content(request).await;
+        metrics().record_upstream_fetch("provider", result.is_ok());
+        result
+    }
+
+    async fn fetch_provider_content(
+        &self,
+        request: &UpstreamRequest,
+    ) -> Result<ProviderContent, UpstreamError> {
+        let response = self.send(request).await?;
+        let userinfo = parse_userinfo(&response);
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let limit = request.size_limit.filter(|limit| *limit > 0);
+        let body = read_body(response, limit).await?;
+        Ok(ProviderContent {
+            body,
+            content_type,
+            userinfo,
+            fetched_at: SystemTime::now(),
+        })
+    }
+
+    async fn send(&self, request: &UpstreamRequest) -> Result<reqwest::Response, UpstreamError> {
+        let mut builder = self.client.get(&request.url);
+        for (name, values) in &request.headers {
+            for value in values {
+                builder = builder.header(name, value);
+            }
+        }
+        let response = builder.send().await?;
+        if !response.status().is_success() {
+            return Err(UpstreamError::Status(response.status().as_u16()));
+        }
+        Ok(response)
+    }
+}
+
+/// 读取响应内容，超过 `limit` 字节时停止读取并返回错误
+pub(crate) async fn read_body(
+    mut response: reqwest::Response,
+    limit: Option<u64>,
+) -> Result<Bytes, UpstreamError> {
+    let Some(limit) = limit else {
+        return Ok(response.bytes().await?);
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length > limit)
+    {
+        return Err(UpstreamError::TooLarge(limit));
+    }
+    let mut body = BytesMut::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (body.len() + chunk.len()) as u64 > limit {
+            return Err(UpstreamError::TooLarge(limit));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+fn parse_userinfo(response: &reqwest::Response) -> Option<SubscriptionUserinfo> {
+    response
+        .headers()
+        .get(SUBSCRIPTION_USERINFO_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune() {
+        let cache = UpstreamCache::new();
+        let now = Instant::now();
+        let request = UpstreamRequest {
+            url: "https://a.example.com".to_string(),
+            ..Default::default()
+        };
+        cache.userinfo.lock().unwrap().insert(
+            request.clone(),
+            CachedUserinfo {
+                info: None,
+                fetched_at: now,
+            },
+        );
+        cache.providers.lock().unwrap().insert(
+            request,
+            CachedProvider {
+                content: ProviderContent {
+                    body: Bytes::new(),
+                    content_type: None,
+                    userinfo: None,
+                    fetched_at: SystemTime::now(),
+                },
+                cached_at: now,
+            },
+        );
+
+        cache.prune_at(now + Duration::from_secs(1));
+        assert_eq!(cache.userinfo.lock().unwrap().len(), 1);
+
+        // 过期的缓存保留到上游失败或后台刷新时使用
+        cache.prune_at(now + USERINFO_CACHE_TTL);
+        assert_eq!(cache.userinfo.lock().unwrap().len(), 1);
+        assert_eq!(cache.providers.lock().unwrap().len(), 1);
+
+        cache.prune_at(now + STALE_TTL);
+        assert!(cache.userinfo.lock().unwrap().is_empty());
+        assert!(cache.providers.lock().unwrap().is_empty());
+    }
+
+    /// 启动一个本地上游，`respond` 根据小写的请求头返回附加的响应头和响应体
+    async fn serve_upstream(
+        delay: Duration,
+        respond: impl Fn(&str) -> (String, String) + Send + Sync + 'static,
+    ) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let respond = Arc::new(respond);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => break,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request).to_lowercase();
+                    let (headers, body) = respond(&request);
+                    tokio::time::sleep(delay).await;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\n{headers}content-length: {}\r\nconnection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        format!("http://{addr}/sub")
+    }
+
+    /// 返回请求中的 User-Agent
+    async fn echo_user_agent_server() -> String {
+        serve_upstream(Duration::ZERO, |request| {
+            let user_agent = request
+                .lines()
+                .find_map(|line| line.strip_prefix("user-agent: "))
+                .unwrap_or_default();
+            (String::new(), user_agent.to_string())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_userinfo_coalesces_and_serves_stale() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        let fetches = Arc::new(AtomicU64::new(0));
+        let counter = fetches.clone();
+        let url = serve_upstream(Duration::from_millis(100), move |_| {
+            let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            let header =
+                format!("{SUBSCRIPTION_USERINFO_HEADER}: upload={count}; download=0; total=0\r\n");
+            (header, String::new())
+        })
+        .await;
+        let cache = Arc::new(UpstreamCache::new());
+        let request = UpstreamRequest {
+            url,
+            ..Default::default()
+        };
+
+        // 并发请求只访问一次上游
+        let mut tasks = JoinSet::new();
+        for _ in 0..5 {
+            let cache = cache.clone();
+            let request = request.clone();
+            tasks.spawn(async move { cache.userinfo(&request).await });
+        }
+        while let Some(info) = tasks.join_next().await {
+            assert_eq!(info.unwrap().unwrap().upload, 1);
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // 缓存过期后立即返回旧值，在后台刷新
+        let Some(expired) = Instant::now().checked_sub(USERINFO_CACHE_TTL) else {
+            return;
+        };
+        cache
+            .userinfo
+            .lock()
+            .unwrap()
+            .get_mut(&request)
+            .unwrap()
+            .fetched_at = expired;
+        assert_eq!(cache.userinfo(&request).await.unwrap().upload, 1);
+        assert_eq!(cache.userinfo(&request).await.unwrap().upload, 1);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(cache.userinfo(&request).await.unwrap().upload, 2);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_provider_uses_source_settings() {
+        let url = echo_user_agent_server().await;
+        let cache = UpstreamCache::new();
+
+        let plain = UpstreamRequest {
+            url: url.clone(),
+            ..Default::default()
+        };
+        let content = cache.provider(&plain).await.unwrap();
+        assert_eq!(&content.body[..], USER_AGENT.to_lowercase().as_bytes());
+
+        // 自定义请求头替换默认的 User-Agent，并单独缓存
+        let custom = UpstreamRequest {
+            url: url.clone(),
+            headers: BTreeMap::from([("User-Agent".to_string(), vec!["custom/1.0".to_string()])]),
+            size_limit: Some(1024),
+        };
+        let content = cache.provider(&custom).await.unwrap();
+        assert_eq!(&content.body[..], b"custom/1.0");
+        assert_eq!(cache.providers.lock().unwrap().len(), 2);
+
+        // 超过大小限制
+        let limited = UpstreamRequest {
+            size_limit: Some(4),
+            ..custom
+        };
+        assert!(matches!(
+            cache.provider(&limited).await,
+            Err(UpstreamError::TooLarge(4))
+        ));
+    }
+
+    #[test]
+    fn test_upstream_request_from_source() {
+        let source: ProxySource = toml::from_str::<HashMap<String, ProxySource>>(
+            r#"
+a = { url = "https://a.example.com", header = { User-Agent = ["clash"] }, size-limit = 0 }
+"#,
+        )
+        .unwrap()
+        .remove("a")
+        .unwrap();
+        let request = UpstreamRequest::from(&source);
+        assert_eq!(request.url, "https://a.example.com");
+        assert_eq!(request.headers["User-Agent"], ["clash"]);
+        assert_eq!(request.size_limit, Some(0));
+
+        let request = UpstreamRequest::from(&ProxySource::from("https://b.example.com"));
+        assert!(request.headers.is_empty());
+        assert_eq!(request.size_limit, None);
+    }
+
+    #[test]
+    fn test_parse_subscription_userinfo() {
+        let info: SubscriptionUserinfo =
+            "upload=100; download=2048; total=1073741824; expire=1735689600"
+                .parse()
+                .unwrap();
+        assert_eq!(info.upload, 100);
+        assert_eq!(info.download, 2048);
+        assert_eq!(info.total, 1073741824);
+        assert_eq!(info.expire, Some(1735689600));
+
+        // 缺少过期时间、包含浮点数和多余分号
+        let info: SubscriptionUserinfo = "upload=1.5e3;download=0;total=10;".parse().unwrap();
+        assert_eq!(info.upload, 1500);
+        assert_eq!(info.expire, None);
+
+        let info: SubscriptionUserinfo = "upload=1; download=2; total=3; expire=0".parse().unwrap();
+        assert_eq!(info.expire, None);
+
+        assert!("upload=abc".parse::<SubscriptionUserinfo>().is_err());
+        assert!("garbage".parse::<SubscriptionUserinfo>().is_err());
+    }
+
+    #[test]
+    fn test_aggregate_subscription_userinfo() {
+        let infos = [
+            SubscriptionUserinfo {
+                upload: 1,
+                download: 2,
+                total: 100,
+                expire: Some(2000),
+            },
+            SubscriptionUserinfo {
+                upload: 10,
+                download: 20,
+                total: 1000,
+                expire: None,
+            },
+            SubscriptionUserinfo {
+                upload: 100,
+                download: 200,
+                total: 10000,
+                expire: Some(1000),
+            },
+        ];
+
+        let total = SubscriptionUserinfo::aggregate(&infos).unwrap();
+        assert_eq!(total.upload, 111);
+        assert_eq!(total.download, 222);
+        assert_eq!(total.total, 11100);
+        assert_eq!(total.expire, Some(1000));
+
+        assert!(SubscriptionUserinfo::aggregate(&[]).is_none());
+    }
+
+    #[test]
+    fn test_display_subscription_userinfo() {
+        let info = SubscriptionUserinfo {
+            upload: 1,
+            download: 2,
+            total: 3,
+            expire: Some(4),
+        };
+        assert_eq!(info.to_string(), "upload=1; download=2; total=3; expire=4");
+
+        let info = SubscriptionUserinfo {
+            expire: None,
+            ..info
+        };
+        assert_eq!(info.to_string(), "upload=1; download=2; total=3");
+    }
+}
@@ -6,6 +6,8 @@ fn test_auth_config_disabled_allows_access() {
         auth: Some(AuthConfig {
             enabled: false,
             token: Some("some-token".to_string()),
+            tokens: Vec::new(),
+            signing_key: None,
         }),
         ..Default::default()
     };
@@ -22,6 +24,8 @@ fn test_auth_config_enabled_requires_correct_token() {
         auth: Some(AuthConfig {
             enabled: true,
             token: Some("correct-token".to_string()),
+            tokens: Vec::new(),
+            signing_key: None,
         }),
         ..Default::default()
     };
@@ -38,6 +42,8 @@ fn test_auth_config_enabled_without_configured_token() {
         auth: Some(AuthConfig {
             enabled: true,
             token: None,
+            tokens: Vec::new(),
+            signing_key: None,
         }),
         ..Default::default()
     };
@@ -65,6 +71,8 @@ fn test_auth_config_with_empty_token() {
         auth: Some(AuthConfig {
             enabled: true,
             token: Some("".to_string()),
+            tokens: Vec::new(),
+            signing_key: None,
         }),
         ..Default::default()
     };
@@ -75,24 +83,53 @@ fn test_auth_config_with_empty_token() {
     assert!(!is_access_allowed(&config, Some("any-token".to_string())));
 }
 
-// Helper function to simulate the authentication logic
+#[test]
+fn test_validate_token_reports_reason() {
+    let config = AppConfig {
+        auth: Some(AuthConfig {
+            enabled: true,
+            token: Some("correct-token".to_string()),
+            tokens: Vec::new(),
+            signing_key: None,
+        }),
+        ..Default::default()
+    };
+
+    assert_eq!(validate_token(&config, None), Err(AuthError::TokenRequired));
+    assert_eq!(validate_token(&config, Some("")), Err(AuthError::TokenRequired));
+    assert_eq!(
+        validate_token(&config, Some("wrong-token")),
+        Err(AuthError::InvalidToken)
+    );
+    assert_eq!(validate_token(&config, Some("correct-token")), Ok(None));
+
+    let misconfigured = AppConfig {
+        auth: Some(AuthConfig {
+            enabled: true,
+            token: None,
+            tokens: Vec::new(),
+            signing_key: None,
+        }),
+        ..Default::default()
+    };
+    assert_eq!(
+        validate_token(&misconfigured, Some("any-token")),
+        Err(AuthError::ServerMisconfigured)
+    );
+}
+
+#[test]
+fn test_auth_error_messages() {
+    // 错误信息与原先的 JSON 响应保持一致
+    assert_eq!(
+        AuthError::ServerMisconfigured.to_string(),
+        "Server configuration error"
+    );
+    assert_eq!(AuthError::TokenRequired.to_string(), "Token required");
+    assert_eq!(AuthError::InvalidToken.to_string(), "Invalid token");
+}
+
+// Helper function wrapping the authentication logic
 fn is_access_allowed(app_config: &AppConfig, provided_token: Option<String>) -> bool {
-    if let Some(auth_config) = &app_config.auth {
-        if auth_config.enabled {
-            let provided = provided_token.as_deref().unwrap_or("");
-            let expected = auth_config.token.as_deref().unwrap_or("");
-            
-            if expected.is_empty() {
-                return false; // Server misconfiguration
-            }
-            
-            if provided.is_empty() {
-                return false; // No token provided
-            }
-            
-            return provided == expected;
-        }
-    }
-    // If authentication is not configured or disabled, allow access
-    true
-}
\ No newline at end of file
+    validate_token(app_config, provided_token.as_deref()).is_ok()
+}
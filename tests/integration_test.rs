@@ -306,10 +306,7 @@ invalid = "not-a-url"
     let result = generate_clash_config_with_validation(app_config);
     
     assert!(result.is_err());
-    match result.unwrap_err() {
-        sub_util::ConfigError::InvalidSubscriptionUrl(_) => {},
-        _ => panic!("Expected InvalidSubscriptionUrl error"),
-    }
+    assert_eq!(result.unwrap_err().kind(), "invalid_subscription_url");
 }
 
 #[test]
@@ -343,4 +340,55 @@ proxies = ["DIRECT"]
         }
         _ => panic!("Expected Proxies group"),
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_generated_yaml_is_deterministic() {
+    let config_content = r#"
+[proxies]
+provider-c = "https://c.example.com/clash"
+provider-a = "https://a.example.com/clash"
+provider-e = "https://e.example.com/clash"
+provider-b = "https://b.example.com/clash"
+provider-d = "https://d.example.com/clash"
+
+[[rules]]
+name = "set-z"
+type = "set"
+url = "https://example.com/z.yaml"
+behavior = "domain"
+target = "DIRECT"
+
+[[rules]]
+name = "set-a"
+type = "set"
+url = "https://example.com/a.yaml"
+behavior = "domain"
+target = "DIRECT"
+
+[[rules]]
+name = "set-m"
+type = "set"
+url = "https://example.com/m.yaml"
+behavior = "domain"
+target = "REJECT"
+"#;
+
+    let mut temp_file = NamedTempFile::new().unwrap();
+    temp_file.write_all(config_content.as_bytes()).unwrap();
+
+    // 每次加载都会得到不同迭代顺序的 HashMap，生成结果仍应一致
+    let render = || {
+        let app_config = AppConfig::load_from_file(temp_file.path()).unwrap();
+        let clash_config = generate_clash_config_with_validation(app_config).unwrap();
+        serde_yaml::to_string(&clash_config).unwrap()
+    };
+    let first = render();
+    for _ in 0..10 {
+        assert_eq!(render(), first);
+    }
+
+    // providers 按名称排序输出
+    let a = first.find("provider-a:").unwrap();
+    let e = first.find("provider-e:").unwrap();
+    assert!(a < e);
+}